@@ -1,4 +1,5 @@
 use crate::emulator::apu::{Square, Wave, Noise, DUTY_CYCLE};
+use crate::emulator::state::State;
 
 use raylib::prelude::*;
 
@@ -37,6 +38,29 @@ impl Envelope {
         self.volume = self.volume_init;
     }
 
+    // stores the register bits only, the running volume and timer are left alone
+    pub fn poke(&mut self, val: u8) {
+        self.add = val&0x8;
+        self.period = val&0x7;
+        self.volume_init = (val >> 4) & 0xF;
+    }
+
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.volume);
+        s.write_u8(self.volume_init);
+        s.write_u8(self.add);
+        s.write_u8(self.period);
+        s.write_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.volume = s.read_u8();
+        self.volume_init = s.read_u8();
+        self.add = s.read_u8();
+        self.period = s.read_u8();
+        self.timer = s.read_u8();
+    }
+
     pub fn tick(&mut self) {
         if self.period > 0 {
             if self.timer > 0 {
@@ -77,12 +101,27 @@ impl LengthDuty {
         self.duty | 0x3F
     }
 
+    pub fn duty(&self) -> u8 {
+        self.duty >> 6
+    }
+
     pub fn write(&mut self, val: u8) {
         self.length = 64 - (val&0x3F);
         self.duty = val&0xC0;
         self.duty_table = DUTY_CYCLE[val as usize >> 6];
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.duty);
+        s.write_u8(self.length);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.duty = s.read_u8();
+        self.duty_table = DUTY_CYCLE[self.duty as usize >> 6];
+        self.length = s.read_u8();
+    }
+
     pub fn tick(&mut self, enable: &mut bool) {
         if self.length > 0 {
             self.length -= 1;
@@ -93,6 +132,15 @@ impl LengthDuty {
     }
 }
 
+pub struct ChannelStatus {  // what a channel is doing, for the debug view
+    pub playing: bool,
+    pub freq: f32,  // Hz, the LFSR clock for noise
+    pub volume: u8,  // envelope volume, the NR32 level code for wave
+    pub duty: Option<u8>,  // NRx1 duty code of the square channels
+    pub length: u16,
+    pub length_enabled: bool,
+}
+
 pub struct ChannelVolume {
     pub left: i16,
     pub right: i16,
@@ -169,7 +217,13 @@ pub struct APU {
     clock: u16,
     frame_clock: u8,
     sample_clock: u32,
+    pub sample_period: u32,
+    sample_pos: usize,
 
+    pub muted: bool,
+    pub capture: Option<Vec<i16>>,  // stereo mix at a fixed 87 cycle period, for recording
+    pub scope: Option<Vec<[i16; 6]>>,  // channels 1-4 then left and right at the same period, for the debug view
+    capture_clock: u32,
     stream: raylib::ffi::AudioStream,
     _audio: RaylibAudio,
     samples: [i16; BUFFER_SIZE],
@@ -193,7 +247,13 @@ impl APU {
             clock: 0,
             frame_clock: 0,
             sample_clock: 0,
+            sample_period: 87,
+            sample_pos: 0,
 
+            muted: false,
+            capture: None,
+            scope: None,
+            capture_clock: 0,
             stream: stream.to_raw(),
             _audio: audio,
             samples: [0; BUFFER_SIZE],
@@ -221,7 +281,7 @@ impl APU {
         apu
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
+    pub fn read(&self, addr: u16) -> u8 {
         match addr {
             // sound channel 1
             0xFF10 => self.sc1.sweep.read() | 0x80,  // MSb unused
@@ -299,6 +359,101 @@ impl APU {
         }
     }
 
+    // stores a register without side effects: NRx4 bit 7 never restarts a channel,
+    // NRx2 leaves the running envelope alone and NR52 never powers the APU off
+    pub fn poke(&mut self, addr: u16, val: u8) -> Result<(), &'static str> {
+        match addr {
+            // sound channel 1
+            0xFF10 => self.sc1.sweep.write(val),
+            0xFF11 => self.sc1.length_duty.write(val),
+            0xFF12 => self.sc1.envelope.poke(val),
+            0xFF13 => self.sc1.freq_lo_write(val),
+            0xFF14 => self.sc1.freq_hi_write(val&0x7F),
+
+            // sound channel 2
+            0xFF16 => self.sc2.length_duty.write(val),
+            0xFF17 => self.sc2.envelope.poke(val),
+            0xFF18 => self.sc2.freq_lo_write(val),
+            0xFF19 => self.sc2.freq_hi_write(val&0x7F),
+
+            // sound channel 3
+            0xFF1A => self.sc3.enable = val&0x80 != 0,
+            0xFF1B => self.sc3.length.length = val,
+            0xFF1C => self.sc3.volume = (val >> 5)&0x3,
+            0xFF1D => self.sc3.freq_lo_write(val),
+            0xFF1E => self.sc3.freq_hi_write(val&0x7F),
+            0xFF30 ..= 0xFF3F => self.sc3.wave_data[addr as usize - 0xFF30] = val,
+
+            // sound channel 4
+            0xFF20 => self.sc4.length.write(val),
+            0xFF21 => self.sc4.envelope.poke(val),
+            0xFF22 => self.sc4.ff22_write(val),
+            0xFF23 => self.sc4.ff23_write(val&0x7F),
+
+            // sound control registers
+            0xFF24 => self.volume.write(val),
+            0xFF25 => self.sch_output.write(val),
+            0xFF26 => self.sch_control = val,
+
+            _ => return Err("not an APU register")
+        }
+        Ok(())
+    }
+
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.volume.data);
+        s.write_u8(self.sch_output.data);
+        s.write_u8(self.sch_control);
+        self.sc1.save_state(s);
+        self.sc2.save_state(s);
+        self.sc3.save_state(s);
+        self.sc4.save_state(s);
+        s.write_u16(self.clock);
+        s.write_u8(self.frame_clock);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.volume.write(s.read_u8());
+        self.sch_output.write(s.read_u8());
+        self.sch_control = s.read_u8();
+        self.sc1.load_state(s);
+        self.sc2.load_state(s);
+        self.sc3.load_state(s);
+        self.sc4.load_state(s);
+        self.clock = s.read_u16();
+        self.frame_clock = s.read_u8();
+    }
+
+    pub fn channel_status(&self) -> [ChannelStatus; 4] {
+        [self.sc1.status(), self.sc2.status(), self.sc3.status(), self.sc4.status()]
+    }
+
+    fn channels(&mut self) -> [i16; 4] {
+        [self.sc1.get_sample(), self.sc2.get_sample(), self.sc3.get_sample(), self.sc4.get_sample()]
+    }
+
+    fn mix(&self, [s1, s2, s3, s4]: [i16; 4]) -> (i16, i16) {
+        let mut l = 0;
+        let mut r = 0;
+
+        if self.sch_control&0x80 != 0 {
+            if self.sch_output.left_sch1 { l += s1; }
+            if self.sch_output.left_sch2 { l += s2; }
+            if self.sch_output.left_sch3 { l += s3; }
+            if self.sch_output.left_sch4 { l += s4; }
+
+            l *= self.volume.left;
+
+            if self.sch_output.right_sch1 { r += s1; }
+            if self.sch_output.right_sch2 { r += s2; }
+            if self.sch_output.right_sch3 { r += s3; }
+            if self.sch_output.right_sch4 { r += s4; }
+
+            r *= self.volume.right;
+        }
+        (l*4, r*4)
+    }
+
     pub fn tick(&mut self){
         self.sc1.tick();
         self.sc2.tick();
@@ -325,51 +480,48 @@ impl APU {
             self.clock = 0;
         }
 
-        if self.sample_clock % 87 == 0 {  // 4194304 / 87 ~ 48000Hz aka sample rate
-            let mut l = 0;
-            let mut r = 0;
-            let pos = (self.sample_clock / 87) * 2;
-
-            let s1 = self.sc1.get_sample();
-            let s2 = self.sc2.get_sample();
-            let s3 = self.sc3.get_sample();
-            let s4 = self.sc4.get_sample();
-
-            if self.sch_control&0x80 != 0 {
-                if self.sch_output.left_sch1 { l += s1; }
-                if self.sch_output.left_sch2 { l += s2; }
-                if self.sch_output.left_sch3 { l += s3; }
-                if self.sch_output.left_sch4 { l += s4; }
-
-                l *= self.volume.left;
-
-                if self.sch_output.right_sch1 { r += s1; }
-                if self.sch_output.right_sch2 { r += s2; }
-                if self.sch_output.right_sch3 { r += s3; }
-                if self.sch_output.right_sch4 { r += s4; }
-
-                r *= self.volume.right;
+        if self.capture.is_some() || self.scope.is_some() {
+            if self.capture_clock == 0 {
+                let channels = self.channels();
+                let (l, r) = self.mix(channels);
+                if let Some(c) = &mut self.capture {
+                    c.push(l);
+                    c.push(r);
+                }
+                if let Some(s) = &mut self.scope {
+                    let [s1, s2, s3, s4] = channels;
+                    s.push([s1, s2, s3, s4, l, r]);
+                }
             }
+            self.capture_clock = (self.capture_clock + 1) % 87;
+        }
 
-            self.samples[pos as usize] = l*4;
-            self.samples[pos as usize + 1] = r*4;
-
-            if pos == BUFFER_SIZE as u32 - 2 {
-                unsafe {
-                    while !raylib::ffi::IsAudioStreamProcessed(self.stream) {}
-                    raylib::ffi::UpdateAudioStream(
-                        self.stream,
-                        self.samples.as_ptr() as *const std::os::raw::c_void,
-                        BUFFER_SIZE as i32
-                    );
+        if self.sample_clock == 0 {  // 4194304 / 87 ~ 48000Hz aka sample rate, longer in slow motion
+            let pos = self.sample_pos;
+            let (l, r) = if self.muted { (0, 0) } else {
+                let channels = self.channels();
+                self.mix(channels)
+            };
+
+            self.samples[pos] = l;
+            self.samples[pos + 1] = r;
+
+            if pos == BUFFER_SIZE - 2 {
+                unsafe {  // emulation speed is paced by frames, a buffer the stream isn't ready for is dropped
+                    if raylib::ffi::IsAudioStreamProcessed(self.stream) {
+                        raylib::ffi::UpdateAudioStream(
+                            self.stream,
+                            self.samples.as_ptr() as *const std::os::raw::c_void,
+                            BUFFER_SIZE as i32
+                        );
+                    }
                 }
-                self.sample_clock = 0;
+                self.sample_pos = 0;
             } else {
-                self.sample_clock += 1;
+                self.sample_pos += 2;
             }
-        } else {        
-            self.sample_clock += 1;
         }
+        self.sample_clock = (self.sample_clock + 1) % self.sample_period;
 
         self.clock += 1;
     }
@@ -1,4 +1,5 @@
-use crate::emulator::apu::{Envelope, LengthDuty};
+use crate::emulator::apu::{ChannelStatus, Envelope, LengthDuty};
+use crate::emulator::state::State;
 
 const DIVISOR_CODE: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
@@ -31,6 +32,32 @@ impl Noise {
         }
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        self.length.save_state(s);
+        self.envelope.save_state(s);
+        s.write_u8(self.counter_consecutive);
+        s.write_bool(self.enable);
+        s.write_u16(self.timer);
+        s.write_u8(self.clock_shift);
+        s.write_u8(self.width_mode);
+        s.write_u8(self.divisor);
+        s.write_u16(self.lfsr);
+        s.write_u16(self.output as u16);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.length.load_state(s);
+        self.envelope.load_state(s);
+        self.counter_consecutive = s.read_u8();
+        self.enable = s.read_bool();
+        self.timer = s.read_u16();
+        self.clock_shift = s.read_u8();
+        self.width_mode = s.read_u8();
+        self.divisor = s.read_u8();
+        self.lfsr = s.read_u16();
+        self.output = s.read_u16() as i16;
+    }
+
     pub fn tick_lfsr(&mut self) {
         let xor_val = ((self.lfsr >> 1) ^ self.lfsr) & 0x1;
         self.lfsr >>= 1;
@@ -81,6 +108,17 @@ impl Noise {
         0
     }
 
+    pub fn status(&self) -> ChannelStatus {
+        ChannelStatus {
+            playing: (self.counter_consecutive != 0 && self.length.length > 0) || self.enable,
+            freq: 4194304. / ((DIVISOR_CODE[self.divisor as usize] as u32) << self.clock_shift) as f32,
+            volume: self.envelope.volume,
+            duty: None,
+            length: self.length.length as u16,
+            length_enabled: self.counter_consecutive != 0,
+        }
+    }
+
     pub fn trigger(&mut self) {
         self.length.length = 63;
         self.timer = DIVISOR_CODE[self.divisor as usize] << self.clock_shift;
@@ -1,4 +1,5 @@
-use crate::emulator::apu::{Envelope, LengthDuty};
+use crate::emulator::apu::{ChannelStatus, Envelope, LengthDuty};
+use crate::emulator::state::State;
 
 pub struct Sweep {
     period: u8,
@@ -31,6 +32,24 @@ impl Sweep {
         self.shift = val&0x7;
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.period);
+        s.write_u8(self.negate);
+        s.write_u8(self.shift);
+        s.write_u16(self.freq);
+        s.write_u8(self.timer);
+        s.write_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.period = s.read_u8();
+        self.negate = s.read_u8();
+        self.shift = s.read_u8();
+        self.freq = s.read_u16();
+        self.timer = s.read_u8();
+        self.enabled = s.read_bool();
+    }
+
     pub fn calculate(&mut self) -> u16 {
         let freq = if self.negate != 0 {  // subtract
             self.freq - (self.freq >> self.shift)
@@ -77,6 +96,32 @@ impl Square {
         }
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        self.length_duty.save_state(s);
+        self.envelope.save_state(s);
+        s.write_u8(self.freq_lo);
+        s.write_u8(self.freq_hi);
+        s.write_u16(self.timer);
+        s.write_u8(self.duty_pos);
+        s.write_u16(self.freq);
+        s.write_u8(self.counter_enabled);
+        self.sweep.save_state(s);
+        s.write_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.length_duty.load_state(s);
+        self.envelope.load_state(s);
+        self.freq_lo = s.read_u8();
+        self.freq_hi = s.read_u8();
+        self.timer = s.read_u16();
+        self.duty_pos = s.read_u8();
+        self.freq = s.read_u16();
+        self.counter_enabled = s.read_u8();
+        self.sweep.load_state(s);
+        self.enabled = s.read_bool();
+    }
+
     pub fn freq_lo_write(&mut self, val: u8) {
         self.freq = (self.freq & 0xF00) | val as u16;
         self.freq_lo = val;
@@ -108,6 +153,17 @@ impl Square {
         0
     }
 
+    pub fn status(&self) -> ChannelStatus {
+        ChannelStatus {
+            playing: (self.counter_enabled != 0 && self.length_duty.length > 0) || self.enabled,
+            freq: 131072. / (2048 - self.freq) as f32,
+            volume: self.envelope.volume,
+            duty: Some(self.length_duty.duty()),
+            length: self.length_duty.length as u16,
+            length_enabled: self.counter_enabled != 0,
+        }
+    }
+
     pub fn trigger(&mut self) {
 
         self.length_duty.length = 63;
@@ -1,4 +1,5 @@
-use crate::emulator::apu::LengthDuty;
+use crate::emulator::apu::{ChannelStatus, LengthDuty};
+use crate::emulator::state::State;
 
 pub struct Wave {  // Wave
     pub enable: bool,       // 0xFF1A NR30
@@ -29,6 +30,32 @@ impl Wave {
         }
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        s.write_bool(self.enable);
+        self.length.save_state(s);
+        s.write_u8(self.volume);
+        s.write_u8(self.freq_lo);
+        s.write_u8(self.freq_hi);
+        s.write_bytes(&self.wave_data);
+        s.write_u16(self.freq);
+        s.write_u8(self.counter_enabled);
+        s.write_u16(self.timer);
+        s.write_u8(self.sample_pos);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.enable = s.read_bool();
+        self.length.load_state(s);
+        self.volume = s.read_u8();
+        self.freq_lo = s.read_u8();
+        self.freq_hi = s.read_u8();
+        s.read_bytes(&mut self.wave_data);
+        self.freq = s.read_u16();
+        self.counter_enabled = s.read_u8();
+        self.timer = s.read_u16();
+        self.sample_pos = s.read_u8();
+    }
+
     pub fn freq_lo_write(&mut self, val: u8) {
         self.freq = (self.freq & 0xF00) | val as u16;
         self.freq_lo = val;
@@ -65,6 +92,17 @@ impl Wave {
         0
     }
 
+    pub fn status(&self) -> ChannelStatus {
+        ChannelStatus {
+            playing: (self.counter_enabled != 0 && self.length.length > 0) || self.enable,
+            freq: 65536. / (2048 - self.freq) as f32,
+            volume: self.volume,
+            duty: None,
+            length: self.length.length as u16,
+            length_enabled: self.counter_enabled != 0,
+        }
+    }
+
     pub fn trigger(&mut self) {
         self.length.length = 255;
         self.timer = (2048 - self.freq) * 4;
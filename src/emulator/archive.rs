@@ -0,0 +1,297 @@
+// zip and gzip containers, inflate based on the structure of zlib's puff.c
+
+use crate::emulator::patch::crc32;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Huffman {
+    counts: [u16; MAX_BITS+1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; MAX_BITS+1];
+        for l in lengths.iter() {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS+2];
+        for len in 1 ..= MAX_BITS {
+            offsets[len+1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbols[offsets[*l as usize] as usize] = sym as u16;
+                offsets[*l as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+}
+
+struct Inflater<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u8,
+    out: Vec<u8>,
+}
+
+impl<'a> Inflater<'a> {
+    fn bits(&mut self, n: u8) -> Result<u32, &'static str> {
+        while self.bit_count < n {
+            let b = *self.data.get(self.pos).ok_or("Unexpected end of compressed data")?;
+            self.bit_buf |= (b as u32) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+        let v = self.bit_buf & ((1u32 << n) - 1);
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(v)
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Result<u16, &'static str> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1 ..= MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = h.counts[len] as i32;
+            if code - count < first {
+                return Ok(h.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid huffman code")
+    }
+
+    fn stored(&mut self) -> Result<(), &'static str> {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+        if self.pos + 4 > self.data.len() {
+            return Err("Unexpected end of compressed data")
+        }
+        let len = self.data[self.pos] as usize | (self.data[self.pos+1] as usize) << 8;
+        self.pos += 4;
+
+        let block = self.data.get(self.pos .. self.pos+len).ok_or("Unexpected end of compressed data")?;
+        self.out.extend_from_slice(block);
+        self.pos += len;
+        Ok(())
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> Result<(), &'static str> {
+        loop {
+            let sym = self.decode(lit)? as usize;
+            match sym {
+                0 ..= 255 => self.out.push(sym as u8),
+                256 => return Ok(()),
+                _ => {
+                    let i = sym - 257;
+                    if i >= 29 {
+                        return Err("Invalid length symbol")
+                    }
+                    let len = LENGTH_BASE[i] as usize + self.bits(LENGTH_EXTRA[i])? as usize;
+
+                    let d = self.decode(dist)? as usize;
+                    if d >= 30 {
+                        return Err("Invalid distance symbol")
+                    }
+                    let distance = DIST_BASE[d] as usize + self.bits(DIST_EXTRA[d])? as usize;
+                    if distance > self.out.len() {
+                        return Err("Distance too far back")
+                    }
+
+                    let start = self.out.len() - distance;
+                    for i in 0 .. len {  // can overlap with itself
+                        let b = self.out[start + i];
+                        self.out.push(b);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fixed(&mut self) -> Result<(), &'static str> {
+        let mut lengths = [0u8; 288];
+        for (i, l) in lengths.iter_mut().enumerate() {
+            *l = match i {
+                0 ..= 143 => 8,
+                144 ..= 255 => 9,
+                256 ..= 279 => 7,
+                _ => 8
+            };
+        }
+        let lit = Huffman::new(&lengths);
+        let dist = Huffman::new(&[5; 30]);
+        self.codes(&lit, &dist)
+    }
+
+    fn dynamic(&mut self) -> Result<(), &'static str> {
+        let nlen = self.bits(5)? as usize + 257;
+        let ndist = self.bits(5)? as usize + 1;
+        let ncode = self.bits(4)? as usize + 4;
+
+        let mut lengths = [0u8; 19];
+        for i in CODE_LENGTH_ORDER.iter().take(ncode) {
+            lengths[*i] = self.bits(3)? as u8;
+        }
+        let lencode = Huffman::new(&lengths);
+
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < nlen + ndist {
+            let sym = self.decode(&lencode)?;
+            let (val, repeat) = match sym {
+                0 ..= 15 => (sym as u8, 1),
+                16 => {
+                    if i == 0 {
+                        return Err("Repeat with no previous length")
+                    }
+                    (lengths[i-1], 3 + self.bits(2)? as usize)
+                },
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize)
+            };
+            if i + repeat > nlen + ndist {
+                return Err("Too many code lengths")
+            }
+            for _ in 0 .. repeat {
+                lengths[i] = val;
+                i += 1;
+            }
+        }
+
+        let lit = Huffman::new(&lengths[.. nlen]);
+        let dist = Huffman::new(&lengths[nlen ..]);
+        self.codes(&lit, &dist)
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut s = Inflater {
+        data,
+        pos: 0,
+        bit_buf: 0,
+        bit_count: 0,
+        out: vec![],
+    };
+
+    loop {
+        let last = s.bits(1)? == 1;
+        match s.bits(2)? {
+            0 => s.stored()?,
+            1 => s.fixed()?,
+            2 => s.dynamic()?,
+            _ => return Err("Invalid deflate block type")
+        }
+        if last {
+            return Ok(s.out)
+        }
+    }
+}
+
+fn le(data: &[u8], pos: usize, n: usize) -> Result<usize, &'static str> {
+    let bytes = data.get(pos .. pos+n).ok_or("Truncated archive")?;
+    Ok(bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as usize))
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+fn unzip(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let eocd = (0 ..= data.len().saturating_sub(22)).rev()
+        .find(|i| data[*i .. *i+4] == [0x50, 0x4B, 0x05, 0x06])
+        .ok_or("Zip end of central directory not found")?;
+    let entries = le(data, eocd + 10, 2)?;
+    let mut pos = le(data, eocd + 16, 4)?;
+
+    for _ in 0 .. entries {
+        if le(data, pos, 4)? != 0x02014B50 {
+            return Err("Invalid zip central directory")
+        }
+        let method = le(data, pos + 10, 2)?;
+        let crc = le(data, pos + 16, 4)? as u32;
+        let compressed_size = le(data, pos + 20, 4)?;
+        let name_len = le(data, pos + 28, 2)?;
+        let extra_len = le(data, pos + 30, 2)?;
+        let comment_len = le(data, pos + 32, 2)?;
+        let local = le(data, pos + 42, 4)?;
+        let name = String::from_utf8_lossy(data.get(pos+46 .. pos+46+name_len).ok_or("Truncated archive")?).to_string();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        if !is_rom_name(&name) {
+            continue
+        }
+
+        let start = local + 30 + le(data, local + 26, 2)? + le(data, local + 28, 2)?;
+        let compressed = data.get(start .. start+compressed_size).ok_or("Truncated archive")?;
+        let rom = match method {
+            0 => compressed.to_vec(),
+            8 => inflate(compressed)?,
+            _ => return Err("Unsupported zip compression method")
+        };
+
+        if crc32(&rom) != crc {
+            return Err("Zip entry checksum mismatch")
+        }
+        println!("Loading {} from archive", name);
+        return Ok(rom)
+    }
+
+    Err("No .gb/.gbc file in zip archive")
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 18 || data[2] != 8 {
+        return Err("Unsupported gzip file")
+    }
+
+    let flags = data[3];
+    let mut pos = 10;
+    if flags&0x04 != 0 {  // extra field
+        pos += 2 + le(data, pos, 2)?;
+    }
+    for flag in [0x08, 0x10].iter() {  // file name, comment
+        if flags&flag != 0 {
+            pos += data.get(pos ..).and_then(|d| d.iter().position(|b| *b == 0)).ok_or("Truncated gzip header")? + 1;
+        }
+    }
+    if flags&0x02 != 0 {  // header crc
+        pos += 2;
+    }
+
+    let rom = inflate(data.get(pos ..).ok_or("Truncated gzip header")?)?;
+    if crc32(&rom) != le(data, data.len() - 8, 4)? as u32 {
+        return Err("Gzip checksum mismatch")
+    }
+    Ok(rom)
+}
+
+// returns the rom inside a zip/gzip container, or data unchanged if it isn't one
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+    if data.starts_with(&[0x50, 0x4B, 0x03, 0x04]) {
+        unzip(&data)
+    } else if data.starts_with(&[0x1F, 0x8B]) {
+        gunzip(&data)
+    } else {
+        Ok(data)
+    }
+}
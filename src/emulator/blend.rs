@@ -0,0 +1,62 @@
+// LCD persistence, games that flicker sprites on alternate frames expect the pixels to linger
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BlendMode {
+    Off,
+    Mix,         // 50% with the previous frame
+    Decay(u16),  // previous output weight out of 256
+}
+
+impl BlendMode {
+    // off, mix, decay or decay:<0-1>
+    pub fn parse(s: &str) -> Result<BlendMode, &'static str> {
+        match s.split_once(':') {
+            None if s == "off" => Ok(BlendMode::Off),
+            None if s == "mix" => Ok(BlendMode::Mix),
+            None if s == "decay" => Ok(BlendMode::Decay(128)),
+            Some(("decay", k)) => match k.parse::<f32>() {
+                Ok(k) if (0. ..1.).contains(&k) => Ok(BlendMode::Decay((k * 256.) as u16)),
+                _ => Err("decay persistence has to be between 0 and 1")
+            },
+            _ => Err("blend mode is one of off, mix, decay[:persistence]")
+        }
+    }
+}
+
+pub struct Blend {
+    pub mode: BlendMode,
+    previous: Vec<u8>,  // last input for Mix, last output for Decay
+}
+
+impl Blend {
+    pub fn new() -> Blend {
+        Blend {
+            mode: BlendMode::Off,
+            previous: vec![],
+        }
+    }
+
+    // rgb in, rgb out, same size
+    pub fn apply(&mut self, frame: &[u8], out: &mut Vec<u8>) {
+        if self.previous.len() != frame.len() {  // first frame or the output size changed
+            self.previous = frame.to_vec();
+        }
+        out.resize(frame.len(), 0);
+
+        match self.mode {
+            BlendMode::Off => out.copy_from_slice(frame),
+            BlendMode::Mix => {
+                for ((o, f), p) in out.iter_mut().zip(frame.iter()).zip(self.previous.iter()) {
+                    *o = (*f as u16 + *p as u16).div_ceil(2) as u8;
+                }
+                self.previous.copy_from_slice(frame);
+            },
+            BlendMode::Decay(w) => {
+                for ((o, f), p) in out.iter_mut().zip(frame.iter()).zip(self.previous.iter()) {
+                    *o = ((*f as u16 * (256 - w) + *p as u16 * w + 128) >> 8) as u8;
+                }
+                self.previous.copy_from_slice(out);
+            }
+        }
+    }
+}
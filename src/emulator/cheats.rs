@@ -0,0 +1,244 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::emulator::mbc::MemoryBankController;
+use crate::emulator::state::State;
+
+const CHEAT_DIR: &str = "cheats";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CheatCode {
+    GameGenie { addr: u16, val: u8, compare: Option<u8> },  // ROM patch
+    GameShark { bank: u8, addr: u16, val: u8 },  // RAM write every VBLANK
+}
+
+impl CheatCode {
+    fn hex_digits(code: &str) -> Result<Vec<u8>, &'static str> {
+        code.chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8).ok_or("Invalid hex digit in cheat code"))
+            .collect()
+    }
+
+    // ABC-DEF-GHI: AB value, FCDE^F000 address, GI ror 2 ^ BA compare, H unused
+    fn parse_game_genie(d: &[u8]) -> CheatCode {
+        let val = (d[0] << 4) | d[1];
+        let addr = (((d[5] ^ 0xF) as u16) << 12) | ((d[2] as u16) << 8) | ((d[3] as u16) << 4) | d[4] as u16;
+        let compare = if d.len() == 9 {
+            Some(((d[6] << 4) | d[8]).rotate_right(2) ^ 0xBA)
+        } else { None };
+
+        CheatCode::GameGenie { addr, val, compare }
+    }
+
+    // TTVVAAAA: TT type (01 - current bank, 8x/9x - WRAM bank x, 8x - cartridge RAM bank x at A000), VV value,
+    // AAAA little endian address
+    fn parse_game_shark(d: &[u8]) -> CheatCode {
+        let byte = |i: usize| (d[i] << 4) | d[i+1];
+
+        CheatCode::GameShark {
+            bank: byte(0),
+            val: byte(2),
+            addr: ((byte(6) as u16) << 8) | byte(4) as u16
+        }
+    }
+
+    pub fn parse(code: &str) -> Result<CheatCode, &'static str> {
+        let code = code.trim();
+        let digits = CheatCode::hex_digits(code)?;
+
+        match digits.len() {
+            6 | 9 if code.contains('-') => Ok(CheatCode::parse_game_genie(&digits)),
+            8 => Ok(CheatCode::parse_game_shark(&digits)),
+            _ => Err("Unrecognized cheat code format")
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Cheat {
+    pub code: String,
+    pub name: String,
+    pub kind: CheatCode,
+    pub enabled: bool,
+    pub session: bool  // from the command line, never saved
+}
+
+type GeniePatches = Rc<RefCell<HashMap<u16, Vec<(u8, Option<u8>)>>>>;
+
+pub struct GameGenie {  // wraps cartridge MBC and patches ROM reads
+    inner: Box<dyn MemoryBankController>,
+    patches: GeniePatches
+}
+
+impl GameGenie {
+    fn patch(&self, addr: u16, val: u8) -> u8 {
+        if let Some(patches) = self.patches.borrow().get(&addr) {
+            for (new, compare) in patches.iter() {
+                match compare {
+                    Some(c) if *c != val => (),
+                    _ => return *new
+                }
+            }
+        }
+        val
+    }
+}
+
+impl MemoryBankController for GameGenie {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        let val = self.inner.read_rom(addr);
+        self.patch(addr, val)
+    }
+    fn peek_rom(&self, addr: u16) -> u8 { self.patch(addr, self.inner.peek_rom(addr)) }
+    fn write_rom(&mut self, addr: u16, val: u8) { self.inner.write_rom(addr, val) }
+    fn read_ram(&mut self, addr: u16) -> u8 { self.inner.read_ram(addr) }
+    fn write_ram(&mut self, addr: u16, val: u8) { self.inner.write_ram(addr, val) }
+    fn ram(&mut self) -> &mut [u8] { self.inner.ram() }
+    fn rom(&mut self) -> &mut [u8] { self.inner.rom() }
+    fn rom_data(&self) -> &[u8] { self.inner.rom_data() }
+    fn ram_data(&self) -> &[u8] { self.inner.ram_data() }
+    fn rom_offset(&self, addr: u16) -> usize { self.inner.rom_offset(addr) }
+    fn ram_offset(&self, addr: u16) -> Option<usize> { self.inner.ram_offset(addr) }
+    fn save_state(&self, s: &mut State) { self.inner.save_state(s) }
+    fn load_state(&mut self, s: &mut State) { self.inner.load_state(s) }
+}
+
+pub struct CheatEngine {
+    pub cheats: Vec<Cheat>,
+    patches: GeniePatches,
+    file: Option<PathBuf>
+}
+
+impl CheatEngine {
+    pub fn new() -> CheatEngine {
+        CheatEngine {
+            cheats: vec![],
+            patches: Rc::new(RefCell::new(HashMap::new())),
+            file: None
+        }
+    }
+
+    pub fn wrap(&self, mbc: Box<dyn MemoryBankController>) -> Box<dyn MemoryBankController> {
+        Box::new(GameGenie {
+            inner: mbc,
+            patches: self.patches.clone()
+        })
+    }
+
+    pub fn add(&mut self, code: &str, name: &str, enabled: bool) -> Result<usize, &'static str> {
+        let kind = CheatCode::parse(code)?;
+
+        if let Some(i) = self.cheats.iter().position(|c| c.kind == kind) {
+            self.cheats[i].enabled = enabled;
+            self.sync();
+            return Ok(i)
+        }
+
+        self.cheats.push(Cheat {
+            code: code.trim().to_uppercase(),
+            name: name.to_string(),
+            kind,
+            enabled,
+            session: false
+        });
+        self.sync();
+        Ok(self.cheats.len() - 1)
+    }
+
+    // enabled for this run only, a code already in the cheat file is just enabled
+    pub fn add_session(&mut self, code: &str) -> Result<usize, &'static str> {
+        let known = self.cheats.len();
+        let i = self.add(code, "", true)?;
+        if i >= known {
+            self.cheats[i].session = true;
+        }
+        Ok(i)
+    }
+
+    pub fn set_enabled(&mut self, i: usize, enabled: bool) {
+        if let Some(c) = self.cheats.get_mut(i) {
+            c.enabled = enabled;
+            self.sync();
+        }
+    }
+
+    pub fn toggle(&mut self, i: usize) {
+        if let Some(c) = self.cheats.get(i) {
+            let e = !c.enabled;
+            self.set_enabled(i, e);
+        }
+    }
+
+    fn sync(&mut self) {  // rebuild genie lookup from enabled codes
+        let mut patches = self.patches.borrow_mut();
+        patches.clear();
+
+        for c in self.cheats.iter().filter(|c| c.enabled) {
+            if let CheatCode::GameGenie { addr, val, compare } = c.kind {
+                patches.entry(addr).or_default().push((val, compare));
+            }
+        }
+    }
+
+    pub fn game_shark(&self) -> impl Iterator<Item = (u8, u16, u8)> + '_ {
+        self.cheats.iter().filter(|c| c.enabled).filter_map(|c| match c.kind {
+            CheatCode::GameShark { bank, addr, val } => Some((bank, addr, val)),
+            _ => None
+        })
+    }
+
+    pub fn file_path(title: &str, checksum: u16) -> PathBuf {
+        let title: String = title.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        PathBuf::from(CHEAT_DIR).join(format!("{}-{:04X}.cht", title, checksum))
+    }
+
+    // one cheat per line: "+CODE name" enabled, "-CODE name" disabled
+    pub fn load(&mut self, title: &str, checksum: u16) -> Result<(), Box<dyn Error>> {
+        let path = CheatEngine::file_path(title, checksum);
+        if !path.exists() {
+            self.file = Some(path);
+            return Ok(())
+        }
+
+        let text = fs::read_to_string(&path)?;
+        self.file = Some(path);  // only once read, so an unreadable file isn't overwritten
+        for line in text.lines() {
+            let line = line.trim();
+            let (enabled, rest) = match line.chars().next() {
+                Some('+') => (true, &line[1..]),
+                Some('-') => (false, &line[1..]),
+                _ => continue
+            };
+            let mut split = rest.splitn(2, ' ');
+            let code = split.next().unwrap_or("");
+            let name = split.next().unwrap_or("").trim();
+
+            if let Err(e) = self.add(code, name, enabled) {
+                println!("Skipping cheat {}: {}", code, e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.file {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let mut out = String::new();
+            for c in self.cheats.iter().filter(|c| !c.session) {
+                out += &format!("{}{} {}\n", if c.enabled { '+' } else { '-' }, c.code, c.name);
+            }
+            fs::write(path, out)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+// CGB 15-bit colors to 24-bit rgb. The GBC LCD is darker and less saturated than plain
+// 5 -> 8 bit expansion suggests, every mode is baked into a 32768 entry table indexed by the raw color.
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Correction {
+    Raw,       // 5-bit values expanded to 8 bits
+    Fast,      // integer channel mix
+    Accurate,  // LCD color matrix in linear light
+}
+
+impl Correction {
+    pub fn parse(s: &str) -> Result<Correction, &'static str> {
+        match s {
+            "raw" => Ok(Correction::Raw),
+            "fast" => Ok(Correction::Fast),
+            "accurate" => Ok(Correction::Accurate),
+            _ => Err("color correction is one of raw, fast, accurate")
+        }
+    }
+}
+
+fn expand(c: u32) -> u8 {
+    ((c << 3) | (c >> 2)) as u8
+}
+
+fn correct(mode: Correction, r: u32, g: u32, b: u32) -> [u8; 3] {
+    match mode {
+        Correction::Raw => [expand(r), expand(g), expand(b)],
+        Correction::Fast => [
+            ((r*26 + g*4 + b*2).min(960) >> 2) as u8,
+            ((g*24 + b*8).min(960) >> 2) as u8,
+            ((r*6 + g*4 + b*22).min(960) >> 2) as u8,
+        ],
+        Correction::Accurate => {
+            const GAMMA: f32 = 2.2;
+            const LUMINANCE: f32 = 0.94;
+            const MATRIX: [[f32; 3]; 3] = [  // output channel from linear r, g, b
+                [0.82, 0.24, -0.06],
+                [0.125, 0.665, 0.21],
+                [0.195, 0.075, 0.73],
+            ];
+
+            let lin = [r, g, b].map(|c| (c as f32 / 31.).powf(GAMMA));
+            MATRIX.map(|row| {
+                let v = (row[0]*lin[0] + row[1]*lin[1] + row[2]*lin[2]) * LUMINANCE;
+                (v.clamp(0., 1.).powf(1. / GAMMA) * 255.).round() as u8
+            })
+        }
+    }
+}
+
+// indexed by r | g << 5 | b << 10
+pub fn build_lut(mode: Correction) -> Vec<[u8; 3]> {
+    (0 .. 0x8000u32).map(|c| correct(mode, c&0x1F, (c >> 5)&0x1F, (c >> 10)&0x1F)).collect()
+}
@@ -0,0 +1,162 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::emulator::Memory;
+use crate::emulator::search::{RamSearch, Width, Compare};
+use crate::emulator::color::Correction;
+use crate::emulator::blend::BlendMode;
+use crate::emulator::scaler::Scaler;
+
+const HELP: &str = "commands:
+  search new [8|16|bcd8|bcd16]    start a new RAM search
+  search eq|ne|inc|dec|val <n>    filter candidates against the last snapshot
+  search list [count]             show candidates
+  search promote <i> <value> [name]  turn candidate i into GameShark cheats
+  cheat add <code> [name]         add and enable a cheat
+  cheat list                      list cheats
+  cheat toggle <i>                enable/disable cheat i
+  movie record <file>             record input from the current state
+  movie play <file>               play back a movie recorded from a state, power on movies need --play
+  movie stop                      stop recording or playback
+  video start <file>              record .gif, or .y4m with a .wav next to it (F12 toggles a gif)
+  video stop                      finish the recording
+  tiles palette <palette>         tile viewer (F1) palette: bgp, obp0, obp1, bg0-7, obj0-7
+  tiles export <file.png> [palette]  save both VRAM banks of tiles
+  oam                             list the 40 OAM entries (F3 shows them)
+  oam line <ly>                   sprites the OAM scan picked for a line
+  palette                         CGB palette RAM as 15-bit colors (F4 shows them)
+  palette set <bg0-7|obj0-7> <0-3> <hex>  change a color
+  palette export <file.pal|file.gpl>  save the 64 colors for JASC or GIMP
+  mem [[bank:]addr]               hex dump around an address (F5 shows it), a bank reads ROM/VRAM/cart RAM/WRAM directly
+  mem bus                         back to the CPU's view of memory
+  mem set <[bank:]addr> <bytes>   write hex bytes or a \"string\", I/O registers without side effects
+  mem find <bytes|\"text\">         next match after the cursor
+  mem follow [[bank:]addr]        jump to the 16-bit pointer at an address
+  mem back                        return to where the last jump started
+  io                              decode the I/O registers (F6 shows them), * marks writes in the last frame
+  layer <bg|window|sprites> on|off  hide a layer without touching LCDC
+  layer <bg0-7|obj0-7> on|off     hide pixels using a CGB palette
+  layer oam <0-39> on|off         hide one OAM entry
+  layer [reset]                   show what is hidden, or show everything again
+  apu                             frequency, note, volume, duty and length of each channel (F7 draws them)
+  color raw|fast|accurate         CGB color correction
+  blend off|mix|decay[:k]         frame blending, k is the persistence (0-1)
+  scale none|<n>x|scale2x|scale3x|xbr|grid[:n]  software scaler";
+
+pub struct Console {  // debug commands read from stdin, executed once per frame
+    rx: Receiver<String>,
+    search: Option<RamSearch>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(l) => if tx.send(l).is_err() { break },
+                    Err(_) => break
+                }
+            }
+        });
+
+        Console {
+            rx,
+            search: None,
+        }
+    }
+
+    pub fn poll(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+
+    pub fn execute(&mut self, mem: &mut Memory, args: &[&str]) -> Result<(), String> {
+        let num = |i: usize| -> Result<u32, String> {
+            let s = args.get(i).ok_or("missing number")?;
+            let r = if let Some(h) = s.strip_prefix("0x") {
+                u32::from_str_radix(h, 16)
+            } else { s.parse() };
+            r.map_err(|e| format!("{}: {}", s, e))
+        };
+
+        match args {
+            [] => Ok(()),
+            ["search", "new", rest @ ..] => {
+                let width = match rest.first() {
+                    None | Some(&"8") => Width::Byte,
+                    Some(&"16") => Width::Word,
+                    Some(&"bcd8") => Width::BCD8,
+                    Some(&"bcd16") => Width::BCD16,
+                    Some(w) => return Err(format!("unknown width {}", w))
+                };
+                let s = RamSearch::new(mem, width);
+                println!("{} candidates", s.candidates.len());
+                self.search = Some(s);
+                Ok(())
+            },
+            ["search", "list", ..] => {
+                let s = self.search.as_ref().ok_or("no search in progress")?;
+                let count = if args.len() > 2 { num(2)? as usize } else { 20 };
+                for (i, c) in s.candidates.iter().take(count).enumerate() {
+                    let (addr, bank) = c.address();
+                    println!("{:4} {:?} {:02X}:{:04X} = {}", i, c.region, bank, addr, s.value(c).unwrap_or(0));
+                }
+                println!("{} candidates", s.candidates.len());
+                Ok(())
+            },
+            ["search", "promote", _, _, name @ ..] => {
+                let s = self.search.as_ref().ok_or("no search in progress")?;
+                let c = *s.candidates.get(num(2)? as usize).ok_or("no such candidate")?;
+                let name = name.join(" ");
+                for code in s.to_game_shark(&c, num(3)?) {
+                    mem.cheats.add(&code, &name, true)?;
+                    println!("added {}", code);
+                }
+                mem.cheats.save().map_err(|e| e.to_string())
+            },
+            ["search", "promote", ..] => Err("usage: search promote <i> <value> [name]".to_string()),
+            ["search", filter, ..] => {
+                let cmp = match *filter {
+                    "eq" => Compare::Equal,
+                    "ne" => Compare::Changed,
+                    "inc" => Compare::Increased,
+                    "dec" => Compare::Decreased,
+                    "val" => Compare::Value(num(2)?),
+                    _ => return Err(HELP.to_string())
+                };
+                let s = self.search.as_mut().ok_or("no search in progress")?;
+                s.filter(mem, cmp);
+                println!("{} candidates", s.candidates.len());
+                Ok(())
+            },
+            ["cheat", "add", code, name @ ..] => {
+                mem.cheats.add(code, &name.join(" "), true)?;
+                mem.cheats.save().map_err(|e| e.to_string())
+            },
+            ["cheat", "list"] => {
+                for (i, c) in mem.cheats.cheats.iter().enumerate() {
+                    println!("{:3} [{}] {} {}", i, if c.enabled { 'x' } else { ' ' }, c.code, c.name);
+                }
+                Ok(())
+            },
+            ["cheat", "toggle", _] => {
+                mem.cheats.toggle(num(2)? as usize);
+                mem.cheats.save().map_err(|e| e.to_string())
+            },
+            ["color", mode] => {
+                mem.ppu.d.set_color_correction(Correction::parse(mode)?);
+                Ok(())
+            },
+            ["blend", mode] => {
+                mem.ppu.d.blend.mode = BlendMode::parse(mode)?;
+                Ok(())
+            },
+            ["scale", mode] => {
+                mem.ppu.d.scaler = Scaler::parse(mode)?;
+                Ok(())
+            },
+            _ => Err(HELP.to_string())
+        }
+    }
+}
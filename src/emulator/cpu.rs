@@ -1,6 +1,12 @@
 #![allow(non_snake_case)]
 
-use crate::emulator::{Memory, execute, PUSH};
+use crate::emulator::{Memory, Console, execute, PUSH};
+use crate::emulator::rewind::Rewind;
+use crate::emulator::speed::Speed;
+use crate::emulator::movie::Movie;
+use crate::emulator::record::Recorder;
+use crate::emulator::debug::Debugger;
+use crate::emulator::state::State;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -36,8 +42,15 @@ pub struct CPU {
 
     pub memory: Memory,
     pub halt: bool,
-
-    subins: u8  // subinstruction memory access counter
+    console: Console,
+    pub rewind: Rewind,
+    pub speed: Speed,
+    pub movie: Movie,
+    pub recorder: Option<Recorder>,
+    pub debug: Debugger,
+
+    subins: u8,  // subinstruction memory access counter
+    cycles_left: u8
 }
 
 impl CPU {
@@ -55,8 +68,15 @@ impl CPU {
 
             memory: Memory::new(),
             halt: false,
-
-            subins: 0
+            console: Console::new(),
+            rewind: Rewind::new(32*1024*1024),
+            speed: Speed::new(),
+            movie: Movie::new(),
+            recorder: None,
+            debug: Debugger::new(),
+
+            subins: 0,
+            cycles_left: 0
         }
     }
 
@@ -254,24 +274,205 @@ impl CPU {
         } else { 1 }
     }
 
-    pub fn run(&mut self) {
-        let mut cycles_left = 0;
+    pub fn save_state(&self, s: &mut State) {
+        unsafe {
+            s.write_u16(self.reg_af.ab);
+            s.write_u16(self.reg_bc.ab);
+            s.write_u16(self.reg_de.ab);
+            s.write_u16(self.reg_hl.ab);
+        }
+        s.write_u16(self.SP);
+        s.write_u16(self.PC);
+        s.write_bool(self.IME);
+        s.write_bool(self.EI);
+        s.write_bool(self.halt);
+        s.write_u8(self.subins);
+        s.write_u8(self.cycles_left);
+        self.memory.save_state(s);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.reg_af.ab = s.read_u16();
+        self.reg_bc.ab = s.read_u16();
+        self.reg_de.ab = s.read_u16();
+        self.reg_hl.ab = s.read_u16();
+        self.SP = s.read_u16();
+        self.PC = s.read_u16();
+        self.IME = s.read_bool();
+        self.EI = s.read_bool();
+        self.halt = s.read_bool();
+        self.subins = s.read_u8();
+        self.cycles_left = s.read_u8();
+        self.memory.load_state(s);
+    }
+
+    fn update_speed_keys(&mut self) {  // tab - turbo, minus/equal - slower/faster, zero - real time
+        use raylib::consts::KeyboardKey::{KEY_TAB, KEY_MINUS, KEY_EQUAL, KEY_ZERO};
+
+        let h = &self.memory.ppu.d.handle;
+        self.speed.turbo_active = h.is_key_down(KEY_TAB);
+
+        let m = self.speed.multiplier;
+        if h.is_key_pressed(KEY_MINUS) && m > 0. {
+            self.speed.multiplier = (m / 2.).max(0.125);
+        } else if h.is_key_pressed(KEY_EQUAL) && m > 0. {
+            self.speed.multiplier = (m * 2.).min(8.);
+        } else if h.is_key_pressed(KEY_ZERO) {
+            self.speed.multiplier = 1.;
+        }
+        if m != self.speed.multiplier {
+            println!("Speed x{}", self.speed.multiplier);
+        }
+    }
+
+    fn movie_command(&mut self, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        match args {
+            ["movie", "record", file] => {
+                let mut s = State::new();
+                self.save_state(&mut s);
+                self.movie.record(std::path::Path::new(file), &self.memory.cart, Some(s.data))
+            },
+            ["movie", "play", file] => {
+                if let Some(data) = self.movie.play(std::path::Path::new(file), &self.memory.cart, false)? {
+                    self.load_state(&mut State::from(data));
+                }
+                Ok(())
+            },
+            ["movie", "stop"] => {
+                self.movie.stop();
+                Ok(())
+            },
+            _ => Err("usage: movie record <file> | movie play <file> | movie stop".into())
+        }
+    }
+
+    pub fn start_video(&mut self, p: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.stop_video();
+        let (_, w, h) = self.memory.ppu.d.output();
+        self.recorder = Some(Recorder::start(p, w, h)?);
+        self.memory.ppu.d.capture = true;
+        self.memory.apu.capture = Some(vec![]);
+        Ok(())
+    }
+
+    pub fn stop_video(&mut self) {
+        if let Some(r) = self.recorder.take() {
+            if let Err(e) = r.finish() {
+                println!("{}", e);
+            }
+        }
+        self.memory.ppu.d.capture = false;
+        self.memory.apu.capture = None;
+    }
+
+    fn video_command(&mut self, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        match args {
+            ["video", "start", file] => self.start_video(std::path::Path::new(file)),
+            ["video", "stop"] => {
+                self.stop_video();
+                Ok(())
+            },
+            _ => Err("usage: video start <file.gif|file.y4m> | video stop".into())
+        }
+    }
+
+    // F12 toggles a gif named after the game
+    fn update_video_key(&mut self) {
+        use raylib::consts::KeyboardKey::KEY_F12;
+
+        if !self.memory.ppu.d.handle.is_key_pressed(KEY_F12) {
+            return;
+        }
+        if self.recorder.is_some() {
+            self.stop_video();
+            return;
+        }
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let name = format!("{}-{}.gif", self.memory.cart.title.trim(), time);
+        if let Err(e) = self.start_video(std::path::Path::new(&name)) {
+            println!("{}", e);
+        }
+    }
+
+    // the picture drawn for the frame that just ended and the audio mixed during it
+    fn record_frame(&mut self) {
+        if let Some(r) = &mut self.recorder {
+            let audio = self.memory.apu.capture.replace(vec![]).unwrap_or_default();
+            let (rgb, w, h) = self.memory.ppu.d.output();
+            if let Err(e) = r.frame(rgb, w, h, &audio) {
+                println!("Recording stopped: {}", e);
+                self.stop_video();
+            }
+        }
+    }
+
+    fn update_console(&mut self) {
+        while let Some(line) = self.console.poll() {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            let r = match args.first() {
+                Some(&"movie") => self.movie_command(&args).map_err(|e| e.to_string()),
+                Some(&"video") => self.video_command(&args).map_err(|e| e.to_string()),
+                Some(cmd) if Debugger::COMMANDS.contains(cmd) => self.debug.command(&mut self.memory, &args).map_err(|e| e.to_string()),
+                _ => self.console.execute(&mut self.memory, &args)
+            };
+            if let Err(e) = r {
+                println!("{}", e);
+            }
+        }
+    }
+
+    fn frame(&mut self) {
+        use raylib::consts::KeyboardKey::KEY_BACKSPACE;
+
+        self.record_frame();
+        if self.memory.ppu.d.handle.window_should_close() {  // the ppu panics on the next tick
+            self.stop_video();
+        }
+        self.update_console();
+        self.update_speed_keys();
+        self.update_video_key();
+
+        let rewinding = self.memory.ppu.d.handle.is_key_down(KEY_BACKSPACE) && !self.movie.is_active();
+        self.memory.apu.muted = rewinding;
+        if rewinding {
+            if let Some(data) = self.rewind.pop() {
+                self.load_state(&mut State::from(data));
+            }
+        } else {
+            let mut s = State::new();
+            self.save_state(&mut s);
+            self.rewind.push(s.data);
+        }
+
+        let live = self.memory.ppu.d.poll_joypad();
+        self.memory.ppu.joypad = self.movie.frame(live);
+
+        self.memory.ppu.d.skip_render = !self.speed.wait();
+        self.memory.apu.sample_period = self.speed.sample_period();
+        self.debug.update(&mut self.memory);
+    }
 
+    pub fn run(&mut self) {
         if self.memory.cart.bootrom_enable {
             self.PC = 0;
         }
 
         loop {
-            if cycles_left > 0 {
-                cycles_left -= 1;
+            if self.memory.frame_done {
+                self.memory.frame_done = false;
+                self.frame();
+            }
+
+            if self.cycles_left > 0 {
+                self.cycles_left -= 1;
             } else {
-                cycles_left = (self.tick() - self.subins)*4;
+                self.cycles_left = (self.tick() - self.subins)*4;
                 self.subins = 0;
                 
-                if cycles_left > 0 {
-                    cycles_left -= 1;
+                if self.cycles_left > 0 {
+                    self.cycles_left -= 1;
                 }
-                if cycles_left == 0 {
+                if self.cycles_left == 0 {
                     continue
                 }
             }
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Memory;
+use crate::emulator::apu::ChannelStatus;
+use super::Panel;
+
+// Scrolling waveforms of channels 1-4 and the left/right mix, newest samples on the right, with what
+// each channel is set to play above them. Samples come from APU::scope while the view is open.
+
+const WIDTH: usize = 320;
+const TOP: usize = 64;  // room for the text
+const ROW: usize = 32;
+const HEIGHT: usize = TOP + 6*ROW;
+const SAMPLES: usize = 1600;  // about two frames at the 87 cycle period
+const PER_COLUMN: usize = SAMPLES / WIDTH;
+const RANGE: [i32; 6] = [15, 15, 15, 15, 1680, 1680];  // largest sample of each row
+const COLORS: [[u8; 3]; 6] = [
+    [0xF0, 0x60, 0x60],
+    [0xF0, 0xC0, 0x40],
+    [0x60, 0xD0, 0x60],
+    [0x60, 0xA0, 0xF0],
+    [0xE0, 0xE0, 0xE0],
+    [0xE0, 0xE0, 0xE0],
+];
+const NOTES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+pub struct AudioViewer {
+    history: VecDeque<[i16; 6]>,
+}
+
+// closest equal tempered note, A4 = 440Hz
+fn note(freq: f32) -> String {
+    let n = (12. * (freq / 440.).log2()).round() as i32 + 69;
+    if !(0 .. 128).contains(&n) {
+        return "-".to_string()
+    }
+    format!("{}{}", NOTES[n as usize % 12], n / 12 - 1)
+}
+
+// channel is 0-3
+pub fn describe(channel: usize, s: &ChannelStatus) -> String {
+    let pitch = if channel == 3 { "    ".to_string() } else { format!("{:4}", note(s.freq)) };  // noise has no pitch
+    let volume = if channel == 2 {
+        format!("level {}", ["mute", "100%", "50%", "25%"][s.volume as usize&3])
+    } else {
+        format!("vol {:2}", s.volume)
+    };
+    let duty = s.duty.map_or(String::new(), |d| format!(" duty {}%", [12.5, 25., 50., 75.][d as usize]));
+
+    format!("{} {:3} {:8.1} Hz {} {}{} len {:3}{}", channel + 1, if s.playing { "on" } else { "off" },
+        s.freq, pitch, volume, duty, s.length, if s.length_enabled { " stops" } else { "" })
+}
+
+impl AudioViewer {
+    pub fn new() -> AudioViewer {
+        AudioViewer {
+            history: VecDeque::with_capacity(SAMPLES),
+        }
+    }
+
+    // keeps the newest samples the APU captured since the last call
+    pub fn take(&mut self, mem: &mut Memory) {
+        if let Some(scope) = &mut mem.apu.scope {
+            self.history.extend(scope.drain(..));
+            let extra = self.history.len().saturating_sub(SAMPLES);
+            self.history.drain(.. extra);
+        }
+    }
+
+    pub fn render(&self) -> (Vec<u8>, usize, usize) {
+        let mut rgb = vec![0x18; WIDTH * HEIGHT * 3];
+        let start = SAMPLES - self.history.len();  // short history is drawn right aligned
+
+        for (row, range) in RANGE.iter().enumerate() {
+            let center = TOP + row*ROW + ROW/2;
+            let half = (ROW/2 - 2) as i32;
+            let y = |v: i16| (center as i32 - v as i32 * half / range) as usize;
+
+            let pos = (center*WIDTH)*3;
+            rgb[pos .. pos + WIDTH*3].fill(0x40);
+
+            for x in 0 .. WIDTH {
+                let from = (x*PER_COLUMN).max(start) - start;
+                let to = ((x + 1)*PER_COLUMN).max(start) - start;
+                let values = self.history.range(from .. to).map(|s| s[row]);
+                let (low, high) = match (values.clone().min(), values.max()) {
+                    (Some(low), Some(high)) => (low, high),
+                    _ => continue
+                };
+
+                for py in y(high) ..= y(low) {
+                    let pos = (py*WIDTH + x)*3;
+                    rgb[pos .. pos+3].copy_from_slice(&COLORS[row]);
+                }
+            }
+        }
+        (rgb, WIDTH, HEIGHT)
+    }
+
+    pub fn panel(&self, mem: &Memory) -> Panel {
+        let (rgb, width, height) = self.render();
+        let mut text = vec!["channels 1-4, left, right".to_string()];
+        text.extend(mem.apu.channel_status().iter().enumerate().map(|(i, s)| describe(i, s)));
+        Panel { rgb, width, height, text }
+    }
+}
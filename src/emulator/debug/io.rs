@@ -0,0 +1,215 @@
+use crate::emulator::Memory;
+use super::{Panel, on};
+
+// Every hardware register with its fields decoded, values come from peek. A * marks registers the
+// game wrote during the last frame.
+
+type Decode = fn(u8) -> String;
+
+const INTERRUPTS: [&str; 5] = ["vblank", "stat", "timer", "serial", "joypad"];
+const MODES: [&str; 4] = ["hblank", "vblank", "oam", "draw"];
+
+fn flags(v: u8, names: &[&str]) -> String {
+    let set: Vec<&str> = names.iter().enumerate().filter(|(i, _)| v&(1 << i) != 0).map(|(_, n)| *n).collect();
+    if set.is_empty() { "none".to_string() } else { set.join(" ") }
+}
+
+fn raw(_: u8) -> String {
+    String::new()
+}
+
+fn lcdc(v: u8) -> String {
+    format!("lcd {}, win map {}, win {}, tiles {}, bg map {}, obj {} {}, bg {}",
+        on(v&0x80 != 0), if v&0x40 != 0 { "9C00" } else { "9800" }, on(v&0x20 != 0),
+        if v&0x10 != 0 { "8000" } else { "8800" }, if v&0x08 != 0 { "9C00" } else { "9800" },
+        if v&0x04 != 0 { "8x16" } else { "8x8" }, on(v&0x02 != 0), on(v&0x01 != 0))
+}
+
+fn stat(v: u8) -> String {
+    format!("mode {}, lyc {}, irq on {}", MODES[v as usize&3], if v&0x04 != 0 { "= ly" } else { "!= ly" },
+        flags(v >> 3, &["hblank", "vblank", "oam", "lyc"]))
+}
+
+fn shades(v: u8) -> String {
+    format!("{} {} {} {}", v&3, (v >> 2)&3, (v >> 4)&3, v >> 6)
+}
+
+fn joypad(v: u8) -> String {
+    let select = match v&0x30 {
+        0x00 => "both",
+        0x10 => "buttons",
+        0x20 => "directions",
+        _ => "none"
+    };
+    format!("select {}, pressed {:04b}", select, !v&0x0F)
+}
+
+fn tac(v: u8) -> String {
+    format!("timer {}, {} Hz", on(v&0x04 != 0), [4096, 262144, 65536, 16384][v as usize&3])
+}
+
+fn interrupts(v: u8) -> String {
+    flags(v, &INTERRUPTS)
+}
+
+fn serial(v: u8) -> String {
+    format!("transfer {}, {} clock", on(v&0x80 != 0), if v&0x01 != 0 { "internal" } else { "external" })
+}
+
+fn sweep(v: u8) -> String {
+    format!("time {}, {}, shift {}", (v >> 4)&7, if v&0x08 != 0 { "down" } else { "up" }, v&7)
+}
+
+fn length_duty(v: u8) -> String {
+    format!("duty {}%, length {}", [12.5, 25., 50., 75.][v as usize >> 6], 64 - (v&0x3F) as u16)
+}
+
+fn length64(v: u8) -> String {
+    format!("length {}", 64 - (v&0x3F) as u16)
+}
+
+fn length256(v: u8) -> String {
+    format!("length {}", 256 - v as u16)
+}
+
+fn envelope(v: u8) -> String {
+    format!("volume {}, {}, period {}", v >> 4, if v&0x08 != 0 { "up" } else { "down" }, v&7)
+}
+
+fn freq_hi(v: u8) -> String {
+    format!("length {}, freq hi {}", on(v&0x40 != 0), v&7)
+}
+
+fn wave_dac(v: u8) -> String {
+    format!("dac {}", on(v&0x80 != 0))
+}
+
+fn wave_volume(v: u8) -> String {
+    ["mute", "100%", "50%", "25%"][(v as usize >> 5)&3].to_string()
+}
+
+fn noise(v: u8) -> String {
+    format!("shift {}, {} bit, divisor code {}", v >> 4, if v&0x08 != 0 { 7 } else { 15 }, v&7)
+}
+
+fn noise_length(v: u8) -> String {
+    format!("length {}", on(v&0x40 != 0))
+}
+
+fn master_volume(v: u8) -> String {
+    format!("left {}, right {}, vin {}{}", (v >> 4)&7, v&7,
+        if v&0x80 != 0 { "L" } else { "" }, if v&0x08 != 0 { "R" } else { "" })
+}
+
+fn panning(v: u8) -> String {
+    format!("left {}, right {}", flags(v >> 4, &["1", "2", "3", "4"]), flags(v, &["1", "2", "3", "4"]))
+}
+
+fn sound_on(v: u8) -> String {
+    format!("apu {}, playing {}", on(v&0x80 != 0), flags(v, &["1", "2", "3", "4"]))
+}
+
+// double speed isn't emulated, the register reads back whatever memory holds
+fn key1(v: u8) -> String {
+    format!("{:02X}, speed switch not emulated", v)
+}
+
+fn vram_bank(v: u8) -> String {
+    format!("bank {}", v&1)
+}
+
+fn wram_bank(v: u8) -> String {
+    format!("bank {}", v&7)
+}
+
+fn hdma5(v: u8) -> String {
+    if v == 0xFF {
+        "idle".to_string()
+    } else {
+        format!("{}, {} blocks left", if v&0x80 != 0 { "stopped" } else { "hblank dma" }, (v&0x7F) as u16 + 1)
+    }
+}
+
+fn palette_index(v: u8) -> String {
+    format!("index {:02X}, auto increment {}", v&0x3F, on(v&0x80 != 0))
+}
+
+fn opri(v: u8) -> String {
+    format!("priority by {}", if v&0x01 != 0 { "x" } else { "oam index" })
+}
+
+const REGISTERS: [(u16, &str, Decode); 55] = [
+    (0xFF00, "P1", joypad),
+    (0xFF01, "SB", raw),
+    (0xFF02, "SC", serial),
+    (0xFF04, "DIV", raw),
+    (0xFF05, "TIMA", raw),
+    (0xFF06, "TMA", raw),
+    (0xFF07, "TAC", tac),
+    (0xFF0F, "IF", interrupts),
+    (0xFFFF, "IE", interrupts),
+
+    (0xFF10, "NR10", sweep),
+    (0xFF11, "NR11", length_duty),
+    (0xFF12, "NR12", envelope),
+    (0xFF13, "NR13", raw),
+    (0xFF14, "NR14", freq_hi),
+    (0xFF16, "NR21", length_duty),
+    (0xFF17, "NR22", envelope),
+    (0xFF18, "NR23", raw),
+    (0xFF19, "NR24", freq_hi),
+    (0xFF1A, "NR30", wave_dac),
+    (0xFF1B, "NR31", length256),
+    (0xFF1C, "NR32", wave_volume),
+    (0xFF1D, "NR33", raw),
+    (0xFF1E, "NR34", freq_hi),
+    (0xFF20, "NR41", length64),
+    (0xFF21, "NR42", envelope),
+    (0xFF22, "NR43", noise),
+    (0xFF23, "NR44", noise_length),
+    (0xFF24, "NR50", master_volume),
+    (0xFF25, "NR51", panning),
+    (0xFF26, "NR52", sound_on),
+
+    (0xFF40, "LCDC", lcdc),
+    (0xFF41, "STAT", stat),
+    (0xFF42, "SCY", raw),
+    (0xFF43, "SCX", raw),
+    (0xFF44, "LY", raw),
+    (0xFF45, "LYC", raw),
+    (0xFF46, "DMA", raw),
+    (0xFF47, "BGP", shades),
+    (0xFF48, "OBP0", shades),
+    (0xFF49, "OBP1", shades),
+    (0xFF4A, "WY", raw),
+    (0xFF4B, "WX", raw),
+
+    (0xFF4D, "KEY1", key1),
+    (0xFF4F, "VBK", vram_bank),
+    (0xFF51, "HDMA1", raw),
+    (0xFF52, "HDMA2", raw),
+    (0xFF53, "HDMA3", raw),
+    (0xFF54, "HDMA4", raw),
+    (0xFF55, "HDMA5", hdma5),
+    (0xFF68, "BCPS", palette_index),
+    (0xFF69, "BCPD", raw),
+    (0xFF6A, "OCPS", palette_index),
+    (0xFF6B, "OCPD", raw),
+    (0xFF6C, "OPRI", opri),
+    (0xFF70, "SVBK", wram_bank),
+];
+
+// written[addr&0xFF] is set for registers written during the last frame
+pub fn lines(mem: &Memory, written: &[bool; 0x100]) -> Vec<String> {
+    let mut lines = vec!["I/O registers, * written last frame".to_string()];
+    for (addr, name, decode) in REGISTERS.iter() {
+        let v = mem.peek(*addr);
+        let mark = if written[*addr as usize&0xFF] { '*' } else { ' ' };
+        lines.push(format!("{}{:04X} {:5} {:02X} {}", mark, addr, name, v, decode(v)));
+    }
+    lines
+}
+
+pub fn panel(mem: &Memory, written: &[bool; 0x100]) -> Panel {
+    Panel { rgb: vec![], width: 0, height: 0, text: lines(mem, written) }
+}
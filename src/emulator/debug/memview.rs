@@ -0,0 +1,171 @@
+use raylib::RaylibHandle;
+
+use crate::emulator::Memory;
+use super::Panel;
+
+// Hex view of the bus as the CPU sees it, or of one bank of ROM, VRAM, cartridge RAM or WRAM whatever the
+// bank registers say. Arrows move the cursor, page up/down a page, [ ] change the bank (below 0 is the bus),
+// F follows the pointer under the cursor and B goes back. Reads are peeks, the view never disturbs the game.
+
+const ROW: usize = 8;
+const ROWS: usize = 16;
+
+pub struct MemoryViewer {
+    pub bank: Option<usize>,  // None is the current mapping
+    pub cursor: u16,
+    top: u16,
+    history: Vec<(Option<usize>, u16)>,
+}
+
+// [bank:]addr in hex
+pub fn parse_location(s: &str) -> Result<(Option<usize>, u16), Box<dyn std::error::Error>> {
+    let hex = |v: &str| u32::from_str_radix(v.trim_start_matches("0x").trim_start_matches('$'), 16);
+    match s.split_once(':') {
+        Some((bank, addr)) => Ok((Some(hex(bank)? as usize), hex(addr)? as u16)),
+        None => Ok((None, hex(s)? as u16))
+    }
+}
+
+// hex bytes, or a "quoted string" of ascii
+pub fn parse_bytes(args: &[&str]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let joined = args.join(" ");
+    if let Some(text) = joined.strip_prefix('"') {
+        return Ok(text.trim_end_matches('"').bytes().collect())
+    }
+    let bytes = args.iter()
+        .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    if bytes.is_empty() {
+        return Err("no bytes given".into())
+    }
+    Ok(bytes)
+}
+
+fn area(addr: u16) -> &'static str {
+    match addr {
+        0x0000 ..= 0x7FFF => "ROM",
+        0x8000 ..= 0x9FFF => "VRAM",
+        0xA000 ..= 0xBFFF => "cart RAM",
+        0xD000 ..= 0xDFFF => "WRAM",
+        _ => "unbanked"
+    }
+}
+
+impl MemoryViewer {
+    pub fn new() -> MemoryViewer {
+        MemoryViewer {
+            bank: None,
+            cursor: 0xC000,
+            top: 0xC000,
+            history: vec![],
+        }
+    }
+
+    // a viewer on one bank, for editing without moving the shown one
+    pub fn with_bank(bank: Option<usize>) -> MemoryViewer {
+        MemoryViewer { bank, ..MemoryViewer::new() }
+    }
+
+    pub fn peek(&self, mem: &Memory, addr: u16) -> u8 {
+        match self.bank {
+            Some(bank) => mem.peek_banked(bank, addr),
+            None => mem.peek(addr)
+        }
+    }
+
+    pub fn poke(&self, mem: &mut Memory, addr: u16, val: u8) -> Result<(), &'static str> {
+        match self.bank {
+            Some(bank) => mem.poke_banked(bank, addr, val),
+            None => mem.poke(addr, val)
+        }
+    }
+
+    fn set_cursor(&mut self, addr: u16) {
+        self.cursor = addr;
+        let page = (ROW*ROWS) as u16;
+        if self.cursor.wrapping_sub(self.top) >= page {
+            self.top = (self.cursor & !(ROW as u16 - 1)).wrapping_sub(page / 2);
+        }
+    }
+
+    // every jump goes through here, so each one leaves exactly one history entry
+    pub fn navigate(&mut self, bank: Option<usize>, addr: u16) {
+        self.history.push((self.bank, self.cursor));
+        self.bank = bank;
+        self.set_cursor(addr);
+    }
+
+    pub fn back(&mut self) {
+        if let Some((bank, addr)) = self.history.pop() {
+            self.bank = bank;
+            self.set_cursor(addr);
+        }
+    }
+
+    // jumps to the little endian pointer at bank:addr
+    pub fn follow(&mut self, mem: &Memory, bank: Option<usize>, addr: u16) -> u16 {
+        let pointer = MemoryViewer::with_bank(bank);
+        let target = pointer.peek(mem, addr) as u16 | (pointer.peek(mem, addr.wrapping_add(1)) as u16) << 8;
+        self.navigate(bank, target);
+        target
+    }
+
+    // next match after the cursor, wrapping around the address space
+    pub fn find(&mut self, mem: &Memory, pattern: &[u8]) -> Option<u16> {
+        let found = (1 ..= 0x10000u32)
+            .map(|i| self.cursor.wrapping_add(i as u16))
+            .find(|start| pattern.iter().enumerate().all(|(i, b)| self.peek(mem, start.wrapping_add(i as u16)) == *b))?;
+        self.navigate(self.bank, found);
+        Some(found)
+    }
+
+    pub fn update_keys(&mut self, h: &RaylibHandle) {
+        use raylib::consts::KeyboardKey::*;
+
+        let moves = [(KEY_LEFT, -1), (KEY_RIGHT, 1), (KEY_UP, -(ROW as i32)), (KEY_DOWN, ROW as i32),
+            (KEY_PAGE_UP, -((ROW*ROWS) as i32)), (KEY_PAGE_DOWN, (ROW*ROWS) as i32)];
+        for (key, delta) in moves.iter() {
+            if h.is_key_pressed(*key) {
+                self.set_cursor(self.cursor.wrapping_add(*delta as u16));
+            }
+        }
+
+        if h.is_key_pressed(KEY_RIGHT_BRACKET) {
+            self.bank = Some(self.bank.map_or(0, |b| b + 1));
+        } else if h.is_key_pressed(KEY_LEFT_BRACKET) {
+            self.bank = self.bank.and_then(|b| b.checked_sub(1));
+        }
+    }
+
+    pub fn lines(&self, mem: &Memory) -> Vec<String> {
+        let mut lines = vec![match self.bank {
+            Some(bank) => format!("{} bank {:02X}", area(self.cursor), bank),
+            None => "bus".to_string()
+        }];
+
+        for row in 0 .. ROWS {
+            let start = self.top.wrapping_add((row*ROW) as u16);
+            let mut line = match self.bank {
+                Some(bank) => format!("{:02X}:{:04X}", bank, start),
+                None => format!("{:04X}", start)
+            };
+            let mut ascii = String::new();
+
+            for i in 0 .. ROW {
+                let addr = start.wrapping_add(i as u16);
+                let b = self.peek(mem, addr);
+                line.push(if addr == self.cursor { '[' } else if addr.wrapping_sub(1) == self.cursor && i > 0 { ']' } else { ' ' });
+                line += &format!("{:02X}", b);
+                ascii.push(if (0x20 .. 0x7F).contains(&b) { b as char } else { '.' });
+            }
+            line.push(if start.wrapping_add(ROW as u16 - 1) == self.cursor { ']' } else { ' ' });
+            line += &format!(" {}", ascii);
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub fn panel(&self, mem: &Memory) -> Panel {
+        Panel { rgb: vec![], width: 0, height: 0, text: self.lines(mem) }
+    }
+}
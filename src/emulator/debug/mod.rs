@@ -0,0 +1,325 @@
+mod png;
+mod tiles;
+mod tilemap;
+mod oam;
+mod palette;
+mod memview;
+mod io;
+mod audio;
+
+use std::error::Error;
+use std::path::Path;
+
+use crate::emulator::Memory;
+use crate::emulator::ppu::{Layers, Pixel_palette};
+
+pub use png::write_png;
+pub use tiles::TileViewer;
+pub use memview::MemoryViewer;
+pub use audio::AudioViewer;
+
+// Debug views shown in the window instead of the game, F1 tiles, F2 tile maps, F3 OAM,
+// F4 CGB palettes, F5 memory, F6 I/O registers, F7 sound channels. The key of the open view closes it.
+
+pub struct Panel {
+    pub rgb: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub text: Vec<String>,  // drawn over the top left corner, lines starting with * highlighted
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum View {
+    Tiles,
+    Tilemap,
+    Oam,
+    Palette,
+    Memory,
+    Io,
+    Audio,
+}
+
+const KEYS: [(raylib::consts::KeyboardKey, View); 7] = [
+    (raylib::consts::KeyboardKey::KEY_F1, View::Tiles),
+    (raylib::consts::KeyboardKey::KEY_F2, View::Tilemap),
+    (raylib::consts::KeyboardKey::KEY_F3, View::Oam),
+    (raylib::consts::KeyboardKey::KEY_F4, View::Palette),
+    (raylib::consts::KeyboardKey::KEY_F5, View::Memory),
+    (raylib::consts::KeyboardKey::KEY_F6, View::Io),
+    (raylib::consts::KeyboardKey::KEY_F7, View::Audio),
+];
+
+// bgp, obp0, obp1, or bg0-7/obj0-7 for CGB palette RAM
+pub fn parse_palette(s: &str) -> Result<Pixel_palette, &'static str> {
+    let index = |i: &str| i.parse::<u8>().ok().filter(|i| *i < 8);
+
+    match s {
+        "bgp" => Ok(Pixel_palette::BG),
+        "obp0" => Ok(Pixel_palette::OBP0),
+        "obp1" => Ok(Pixel_palette::OBP1),
+        _ => {
+            if let Some(i) = s.strip_prefix("obj").and_then(index) {
+                Ok(Pixel_palette::CGB_OBJ(i))
+            } else if let Some(i) = s.strip_prefix("bg").and_then(index) {
+                Ok(Pixel_palette::CGB_BG(i))
+            } else {
+                Err("palette is one of bgp, obp0, obp1, bg0-7, obj0-7")
+            }
+        }
+    }
+}
+
+pub fn palette_name(p: Pixel_palette) -> String {
+    match p {
+        Pixel_palette::BG => "bgp".to_string(),
+        Pixel_palette::OBP0 => "obp0".to_string(),
+        Pixel_palette::OBP1 => "obp1".to_string(),
+        Pixel_palette::CGB_BG(i) => format!("bg{}", i),
+        Pixel_palette::CGB_OBJ(i) => format!("obj{}", i),
+    }
+}
+
+fn on(b: bool) -> &'static str {
+    if b { "on" } else { "off" }
+}
+
+fn parse_on(s: &str) -> Result<bool, &'static str> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("expected on or off")
+    }
+}
+
+pub struct Debugger {
+    view: Option<View>,
+    pub tiles: TileViewer,
+    pub memory: MemoryViewer,
+    pub audio: AudioViewer,
+    written: [bool; 0x100],  // I/O registers written during the last frame
+}
+
+impl Debugger {
+    pub const COMMANDS: [&'static str; 7] = ["tiles", "oam", "palette", "mem", "io", "layer", "apu"];  // console commands handled by command()
+
+    pub fn new() -> Debugger {
+        Debugger {
+            view: None,
+            tiles: TileViewer::new(),
+            memory: MemoryViewer::new(),
+            audio: AudioViewer::new(),
+            written: [false; 0x100],
+        }
+    }
+
+    // once per frame, keys and the panel for the next drawn frame
+    pub fn update(&mut self, mem: &mut Memory) {
+        use raylib::consts::KeyboardKey::{KEY_P, KEY_F, KEY_B};
+
+        self.written = mem.io_written;
+        mem.io_written = [false; 0x100];
+        self.audio.take(mem);
+
+        if mem.ppu.d.skip_render {
+            return;
+        }
+
+        let h = &mem.ppu.d.handle;
+        if let Some((_, v)) = KEYS.iter().find(|(k, _)| h.is_key_pressed(*k)) {
+            self.view = if self.view == Some(*v) { None } else { Some(*v) };
+        }
+        if self.view == Some(View::Tiles) && h.is_key_pressed(KEY_P) {
+            self.tiles.next_palette(mem.ppu.gb_mode);
+        }
+        if self.view == Some(View::Memory) {
+            self.memory.update_keys(h);
+            if h.is_key_pressed(KEY_F) {
+                self.memory.follow(mem, self.memory.bank, self.memory.cursor);
+            } else if h.is_key_pressed(KEY_B) {
+                self.memory.back();
+            }
+        }
+
+        if self.view == Some(View::Audio) {
+            mem.apu.scope.get_or_insert_with(Vec::new);
+        } else {
+            mem.apu.scope = None;
+        }
+
+        let mouse = mem.ppu.d.mouse;
+        mem.ppu.d.panel = match self.view {
+            Some(View::Tiles) => Some(self.tiles.panel(mem, mouse)),
+            Some(View::Tilemap) => Some(tilemap::panel(mem, mouse)),
+            Some(View::Oam) => Some(oam::panel(mem, mouse)),
+            Some(View::Palette) => Some(palette::panel(mem, mouse)),
+            Some(View::Memory) => Some(self.memory.panel(mem)),
+            Some(View::Io) => Some(io::panel(mem, &self.written)),
+            Some(View::Audio) => Some(self.audio.panel(mem)),
+            None => None
+        };
+    }
+
+    pub fn command(&mut self, mem: &mut Memory, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        match args {
+            ["tiles", "palette", name] => {
+                self.tiles.palette = parse_palette(name)?;
+                Ok(())
+            },
+            ["tiles", "export", file, palette @ ..] => {
+                let p = match palette {
+                    [] => self.tiles.palette,
+                    [name] => parse_palette(name)?,
+                    _ => return Err("usage: tiles export <file.png> [palette]".into())
+                };
+                let (rgb, width, height) = tiles::render(mem, p);
+                write_png(Path::new(file), &rgb, width, height)?;
+                println!("Saved tiles to {}", file);
+                Ok(())
+            },
+            ["tiles", ..] => Err("usage: tiles palette <palette> | tiles export <file.png> [palette]".into()),
+            ["oam"] => {
+                for s in oam::sprites(mem).iter() {
+                    println!("{}", oam::describe(mem, s));
+                }
+                Ok(())
+            },
+            ["oam", "line", ly] => {
+                let ly: usize = ly.parse()?;
+                let selected = mem.ppu.line_sprites.get(ly).ok_or("LY has to be below 144")?;
+                for i in selected.iter() {
+                    println!("{}", oam::describe(mem, &oam::sprites(mem)[*i as usize]));
+                }
+                println!("{} selected on LY {}, mode 3 took {} dots", selected.len(), ly, mem.ppu.line_timing[ly]);
+                Ok(())
+            },
+            ["oam", ..] => Err("usage: oam | oam line <ly>".into()),
+            ["palette"] => {
+                for p in palette::all() {
+                    let colors: Vec<String> = (0 .. 4).map(|i| format!("{:04X}", mem.ppu.cgb_color(p, i).unwrap_or(0))).collect();
+                    println!("{:5}{}", palette_name(p), colors.join(" "));
+                }
+                Ok(())
+            },
+            ["palette", "set", name, i, val] => {
+                let i: usize = i.parse()?;
+                if i > 3 {
+                    return Err("color index is 0-3".into())
+                }
+                let val = u16::from_str_radix(val.trim_start_matches("0x"), 16)?;
+                mem.ppu.set_cgb_color(parse_palette(name)?, i, val&0x7FFF)?;
+                Ok(())
+            },
+            ["palette", "export", file] => {
+                palette::export(mem, Path::new(file))?;
+                println!("Saved palettes to {}", file);
+                Ok(())
+            },
+            ["palette", ..] => Err("usage: palette | palette set <bg0-7|obj0-7> <0-3> <hex> | palette export <file.pal|file.gpl>".into()),
+            ["mem", "bus"] => {
+                self.memory.navigate(None, self.memory.cursor);
+                self.print_memory(mem);
+                Ok(())
+            },
+            ["mem", "set", location, bytes @ ..] => {
+                let (bank, addr) = memview::parse_location(location)?;
+                let viewer = MemoryViewer::with_bank(bank);
+                for (i, b) in memview::parse_bytes(bytes)?.iter().enumerate() {
+                    viewer.poke(mem, addr.wrapping_add(i as u16), *b)?;
+                }
+                Ok(())
+            },
+            ["mem", "find", pattern @ ..] => {
+                match self.memory.find(mem, &memview::parse_bytes(pattern)?) {
+                    Some(addr) => println!("Found at {:04X}", addr),
+                    None => println!("Not found")
+                }
+                Ok(())
+            },
+            ["mem", "follow"] => {
+                self.memory.follow(mem, self.memory.bank, self.memory.cursor);
+                self.print_memory(mem);
+                Ok(())
+            },
+            ["mem", "follow", location] => {
+                let (bank, addr) = memview::parse_location(location)?;
+                self.memory.follow(mem, bank.or(self.memory.bank), addr);
+                self.print_memory(mem);
+                Ok(())
+            },
+            ["mem", "back"] => {
+                self.memory.back();
+                self.print_memory(mem);
+                Ok(())
+            },
+            ["io"] => {
+                for line in io::lines(mem, &self.written) {
+                    println!("{}", line);
+                }
+                Ok(())
+            },
+            ["layer"] => {
+                let l = &mem.ppu.layers;
+                let bits = |mask: u64, n: u64| (0 .. n).filter(|i| mask&(1 << i) != 0).map(|i| i.to_string()).collect::<Vec<_>>().join(" ");
+                println!("bg {}, window {}, sprites {}", on(l.bg), on(l.window), on(l.sprites));
+                println!("hidden bg palettes [{}], obj palettes [{}], oam entries [{}]",
+                    bits(l.bg_palettes as u64, 8), bits(l.obj_palettes as u64, 8), bits(l.oam, 40));
+                Ok(())
+            },
+            ["layer", "reset"] => {
+                mem.ppu.layers = Layers::new();
+                Ok(())
+            },
+            ["layer", "oam", i, state] => {
+                let i: u64 = i.parse()?;
+                if i >= 40 {
+                    return Err("OAM entry is 0-39".into())
+                }
+                let l = &mut mem.ppu.layers;
+                l.oam = if parse_on(state)? { l.oam&!(1 << i) } else { l.oam | 1 << i };
+                Ok(())
+            },
+            ["layer", name, state] => {
+                let visible = parse_on(state)?;
+                let l = &mut mem.ppu.layers;
+                match *name {
+                    "bg" => l.bg = visible,
+                    "window" => l.window = visible,
+                    "sprites" => l.sprites = visible,
+                    _ => {
+                        let (mask, n) = match parse_palette(name)? {
+                            Pixel_palette::CGB_BG(n) => (&mut l.bg_palettes, n),
+                            Pixel_palette::CGB_OBJ(n) => (&mut l.obj_palettes, n),
+                            _ => return Err("only CGB palettes can be hidden".into())
+                        };
+                        *mask = if visible { *mask&!(1 << n) } else { *mask | 1 << n };
+                    }
+                }
+                Ok(())
+            },
+            ["layer", ..] => Err("usage: layer | layer reset | layer <bg|window|sprites|bg0-7|obj0-7> on|off | layer oam <0-39> on|off".into()),
+            ["apu"] => {
+                for (i, s) in mem.apu.channel_status().iter().enumerate() {
+                    println!("{}", audio::describe(i, s));
+                }
+                Ok(())
+            },
+            ["mem"] => {
+                self.print_memory(mem);
+                Ok(())
+            },
+            ["mem", location] => {
+                let (bank, addr) = memview::parse_location(location)?;
+                self.memory.navigate(bank, addr);
+                self.print_memory(mem);
+                Ok(())
+            },
+            _ => Err("usage: mem [[bank:]addr] | mem bus | mem set <[bank:]addr> <bytes> | mem find <bytes|\"text\"> | mem follow [[bank:]addr] | mem back".into())
+        }
+    }
+
+    fn print_memory(&self, mem: &Memory) {
+        for line in self.memory.lines(mem) {
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::emulator::{Memory, MODE};
+use crate::emulator::ppu::{compose_two_bytes, Sprite};
+use super::{Panel, palette_name};
+
+// The 40 OAM entries as 8 columns of previews on the left. On the right one row per LY and
+// one column per entry: green if the OAM scan picked it, red if it was on the line but over the 10 sprite limit.
+
+const CELL_W: usize = 12;
+const CELL_H: usize = 20;
+const LINES_X: usize = 8*CELL_W + 4;
+const COLUMN: usize = 3;
+const WIDTH: usize = LINES_X + 40*COLUMN;
+const HEIGHT: usize = 144;
+const SELECTED: [u8; 3] = [0x30, 0xD0, 0x30];
+const DROPPED: [u8; 3] = [0xE0, 0x30, 0x30];
+
+pub fn sprites(mem: &Memory) -> Vec<Sprite> {
+    (0 .. 40).map(|i| Sprite::new(&mem.OAM[i*4 .. i*4+4], (i*4) as u8)).collect()
+}
+
+fn tall(mem: &Memory) -> bool {
+    mem.ppu.lcdc&0x04 != 0
+}
+
+// entries the OAM scan would consider for ly without the 10 sprite limit
+fn on_line(mem: &Memory, ly: usize) -> Vec<u8> {
+    (0 .. 40u8).filter(|i| {
+        let pos = *i as usize * 4;
+        Sprite::is_in_scanline(mem.OAM[pos+1], mem.OAM[pos], ly as u8, tall(mem))
+    }).collect()
+}
+
+pub fn describe(mem: &Memory, s: &Sprite) -> String {
+    let cgb = mem.ppu.gb_mode == MODE::CGB;
+    format!("#{:02} x {:3} y {:3} ({:4}, {:4}) tile {:02X} {}{}{}{}{}",
+        s.oam_addr / 4, s.x, s.y, s.x as i16 - 8, s.y as i16 - 16, s.tile_location,
+        palette_name(if cgb { s.cgb_palette } else { s.palette }),
+        if cgb { format!(" bank {}", s.vram_bank) } else { String::new() },
+        if s.x_flip { " xflip" } else { "" },
+        if s.y_flip { " yflip" } else { "" },
+        if s.priority { " behind bg" } else { "" })
+}
+
+fn draw_sprite(mem: &Memory, s: &Sprite, rgb: &mut [u8], x: usize, y: usize) {
+    let cgb = mem.ppu.gb_mode == MODE::CGB;
+    let colors = mem.ppu.palette_rgb(if cgb { s.cgb_palette } else { s.palette });
+    let (height, tile) = if tall(mem) { (16, s.tile_location&0xFE) } else { (8, s.tile_location) };
+    let bank = if cgb { s.vram_bank as usize } else { 0 };
+
+    for row in 0 .. height {
+        let line = if s.y_flip { height - 1 - row } else { row };
+        let addr = bank*0x2000 + tile as usize*16 + line*2;
+        let mut pixels = compose_two_bytes(mem.vram[addr], mem.vram[addr+1]);
+        if s.x_flip {
+            pixels.reverse();
+        }
+
+        for (i, p) in pixels.iter().enumerate() {
+            if *p != 0 {  // color 0 is transparent
+                let pos = ((y + row)*WIDTH + x + i)*3;
+                rgb[pos .. pos+3].copy_from_slice(&colors[*p as usize]);
+            }
+        }
+    }
+}
+
+pub fn render(mem: &Memory) -> (Vec<u8>, usize, usize) {
+    let mut rgb = vec![0x20; WIDTH * HEIGHT * 3];
+
+    for (i, s) in sprites(mem).iter().enumerate() {
+        let (x, y) = ((i%8)*CELL_W, (i/8)*CELL_H);
+        for row in 1 .. CELL_H-1 {
+            let pos = ((y + row)*WIDTH + x + 1)*3;
+            rgb[pos .. pos + (CELL_W-2)*3].fill(0x48);
+        }
+        draw_sprite(mem, s, &mut rgb, x + 2, y + 2);
+    }
+
+    for ly in 0 .. HEIGHT {
+        let selected = &mem.ppu.line_sprites[ly];
+        for i in on_line(mem, ly) {
+            let color = if selected.contains(&i) { SELECTED } else { DROPPED };
+            let pos = (ly*WIDTH + LINES_X + i as usize*COLUMN)*3;
+            for c in rgb[pos .. pos + (COLUMN-1)*3].chunks_mut(3) {
+                c.copy_from_slice(&color);
+            }
+        }
+    }
+    (rgb, WIDTH, HEIGHT)
+}
+
+pub fn panel(mem: &Memory, mouse: Option<(usize, usize)>) -> Panel {
+    let (rgb, width, height) = render(mem);
+    let mut text = vec![format!("{} sprites", if tall(mem) { "8x16" } else { "8x8" })];
+
+    match mouse {
+        Some((x, y)) if x < 8*CELL_W && y < 5*CELL_H => {
+            let i = (y / CELL_H) * 8 + x / CELL_W;
+            text.push(describe(mem, &sprites(mem)[i]));
+        },
+        Some((x, ly)) if x >= LINES_X => {
+            let selected = &mem.ppu.line_sprites[ly];
+            let dropped = on_line(mem, ly).len().saturating_sub(selected.len());
+            let list: Vec<String> = selected.iter().map(|i| i.to_string()).collect();
+            text.push(format!("LY {}: {} selected, {} over the limit, mode 3 {} dots", ly, selected.len(), dropped, mem.ppu.line_timing[ly]));
+            text.push(format!("[{}]", list.join(" ")));
+            let i = (x - LINES_X) / COLUMN;
+            if i < 40 {
+                text.push(describe(mem, &sprites(mem)[i]));
+            }
+        },
+        _ => ()
+    }
+    Panel { rgb, width, height, text }
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::emulator::Memory;
+use crate::emulator::ppu::Pixel_palette;
+use super::{Panel, palette_name};
+
+// CGB palette RAM, BG palettes 0-7 as rows on the left and OBJ on the right, one swatch per color
+
+const SWATCH: usize = 16;
+const GAP: usize = 8;
+const WIDTH: usize = 8*SWATCH + GAP;
+const HEIGHT: usize = 8*SWATCH;
+
+// all 16 palettes, BG first
+pub fn all() -> impl Iterator<Item = Pixel_palette> {
+    (0 .. 8).map(Pixel_palette::CGB_BG).chain((0 .. 8).map(Pixel_palette::CGB_OBJ))
+}
+
+fn at(x: usize, y: usize) -> Option<(Pixel_palette, usize)> {
+    let n = (y / SWATCH) as u8;
+    match x {
+        _ if y >= HEIGHT => None,
+        x if x < 4*SWATCH => Some((Pixel_palette::CGB_BG(n), x / SWATCH)),
+        x if (4*SWATCH + GAP .. WIDTH).contains(&x) => Some((Pixel_palette::CGB_OBJ(n), (x - 4*SWATCH - GAP) / SWATCH)),
+        _ => None
+    }
+}
+
+// 5-bit channels expanded to 8 bits, without color correction
+fn expand(c: u16) -> [u8; 3] {
+    [c, c >> 5, c >> 10].map(|v| (((v&0x1F) << 3) | ((v&0x1F) >> 2)) as u8)
+}
+
+pub fn render(mem: &Memory) -> (Vec<u8>, usize, usize) {
+    let mut rgb = vec![0x20; WIDTH * HEIGHT * 3];
+
+    for (i, p) in all().enumerate() {
+        let colors = mem.ppu.palette_rgb(p);
+        let x0 = (i / 8) * (4*SWATCH + GAP);
+        let y0 = (i % 8) * SWATCH;
+
+        for (c, color) in colors.iter().enumerate() {
+            for y in y0 + 1 .. y0 + SWATCH - 1 {
+                let pos = (y*WIDTH + x0 + c*SWATCH + 1)*3;
+                for px in rgb[pos .. pos + (SWATCH-2)*3].chunks_mut(3) {
+                    px.copy_from_slice(color);
+                }
+            }
+        }
+    }
+    (rgb, WIDTH, HEIGHT)
+}
+
+pub fn panel(mem: &Memory, mouse: Option<(usize, usize)>) -> Panel {
+    let (rgb, width, height) = render(mem);
+    let mut text = vec![];
+
+    if let Some((p, i)) = mouse.and_then(|(x, y)| at(x, y)) {
+        let raw = mem.ppu.cgb_color(p, i).unwrap_or(0);
+        let shown = mem.ppu.palette_rgb(p)[i];
+        text.push(format!("{} color {}: {:04X} (r {} g {} b {})", palette_name(p), i, raw, raw&0x1F, (raw >> 5)&0x1F, (raw >> 10)&0x1F));
+        text.push(format!("shown as #{:02X}{:02X}{:02X}", shown[0], shown[1], shown[2]));
+    }
+    Panel { rgb, width, height, text }
+}
+
+// JASC .pal or GIMP .gpl by extension, 64 colors in palette order
+pub fn export(mem: &Memory, p: &Path) -> io::Result<()> {
+    let colors: Vec<(String, [u8; 3])> = all()
+        .flat_map(|pal| (0 .. 4).map(move |i| (pal, i)))
+        .map(|(pal, i)| (format!("{} {}", palette_name(pal), i), expand(mem.ppu.cgb_color(pal, i).unwrap_or(0))))
+        .collect();
+
+    let gpl = p.extension().is_some_and(|e| e.eq_ignore_ascii_case("gpl"));
+    let mut out = String::new();
+    if gpl {
+        out += &format!("GIMP Palette\nName: {}\nColumns: 4\n#\n", mem.cart.title.trim());
+        for (name, c) in colors.iter() {
+            out += &format!("{:3} {:3} {:3}\t{}\n", c[0], c[1], c[2], name);
+        }
+    } else {
+        out += &format!("JASC-PAL\n0100\n{}\n", colors.len());
+        for (_, c) in colors.iter() {
+            out += &format!("{} {} {}\n", c[0], c[1], c[2]);
+        }
+    }
+    fs::write(p, out)
+}
@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::emulator::patch::crc32;
+
+// Truecolor PNG without a compressor, the zlib stream is made of stored deflate blocks
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(&body)?;
+    out.write_all(&crc32(&body).to_be_bytes())
+}
+
+pub fn write_png(p: &Path, rgb: &[u8], width: usize, height: usize) -> io::Result<()> {
+    let mut raw = Vec::with_capacity((width*3 + 1) * height);
+    for row in rgb.chunks(width*3) {
+        raw.push(0);  // filter type none
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(0xFFFF).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push((i == blocks.len() - 1) as u8);  // BFINAL, BTYPE 00 stored
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);  // 8 bit rgb, no interlacing
+
+    let mut out = BufWriter::new(File::create(p)?);
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    chunk(&mut out, b"IHDR", &header)?;
+    chunk(&mut out, b"IDAT", &zlib)?;
+    chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
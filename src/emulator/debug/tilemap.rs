@@ -0,0 +1,122 @@
+use crate::emulator::{Memory, MODE};
+use crate::emulator::ppu::{compose_two_bytes, Pixel_palette, TileAttributes};
+use super::Panel;
+
+// Both 32x32 tile maps (0x9800 left, 0x9C00 right) drawn with the current LCDC tile data area and
+// CGB attributes. The SCX/SCY viewport is outlined in red on the BG map, the window area in blue on its map.
+
+const SIZE: usize = 256;
+const GAP: usize = 4;
+const WIDTH: usize = SIZE*2 + GAP;
+const VIEWPORT: [u8; 3] = [0xFF, 0x20, 0x20];
+const WINDOW: [u8; 3] = [0x30, 0x60, 0xFF];
+
+// vram offset of the tile data for a map entry
+fn tile_data(mem: &Memory, tile: u8, bank: usize) -> usize {
+    let addr = if mem.ppu.lcdc&0x10 != 0 {
+        tile as usize * 16
+    } else {
+        (0x1000 + tile as i8 as isize * 16) as usize
+    };
+    bank*0x2000 + addr
+}
+
+fn attributes(mem: &Memory, entry: usize) -> Option<TileAttributes> {
+    if mem.ppu.gb_mode == MODE::CGB {
+        Some(TileAttributes::new(mem.vram[0x2000 + entry]))
+    } else { None }
+}
+
+// outline of w*h pixels starting at (x, y) of a map, wrapping around its edges
+fn outline(rgb: &mut [u8], map: usize, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+    let mut set = |dx: usize, dy: usize| {
+        let pos = (((y + dy) % SIZE) * WIDTH + map*(SIZE + GAP) + (x + dx) % SIZE) * 3;
+        rgb[pos .. pos+3].copy_from_slice(&color);
+    };
+    for dx in 0 .. w {
+        set(dx, 0);
+        set(dx, h-1);
+    }
+    for dy in 0 .. h {
+        set(0, dy);
+        set(w-1, dy);
+    }
+}
+
+pub fn render(mem: &Memory) -> (Vec<u8>, usize, usize) {
+    let palettes: Vec<[[u8; 3]; 4]> = if mem.ppu.gb_mode == MODE::CGB {
+        (0 .. 8).map(|i| mem.ppu.palette_rgb(Pixel_palette::CGB_BG(i))).collect()
+    } else {
+        vec![mem.ppu.palette_rgb(Pixel_palette::BG)]
+    };
+    let mut rgb = vec![0x40; WIDTH * SIZE * 3];
+
+    for map in 0 .. 2 {
+        for entry in 0 .. 32*32 {
+            let offset = 0x1800 + map*0x400 + entry;
+            let attr = attributes(mem, offset).unwrap_or_else(|| TileAttributes::new(0));
+            let data = tile_data(mem, mem.vram[offset], attr.vram_bank as usize);
+            let colors = &palettes[attr.palette as usize % palettes.len()];
+
+            for row in 0 .. 8 {
+                let line = if attr.y_flip { 7 - row } else { row };
+                let mut pixels = compose_two_bytes(mem.vram[data + line*2], mem.vram[data + line*2 + 1]);
+                if attr.x_flip {
+                    pixels.reverse();
+                }
+
+                let x = map*(SIZE + GAP) + (entry%32)*8;
+                let y = (entry/32)*8 + row;
+                for (i, p) in pixels.iter().enumerate() {
+                    let pos = (y*WIDTH + x + i)*3;
+                    rgb[pos .. pos+3].copy_from_slice(&colors[*p as usize]);
+                }
+            }
+        }
+    }
+
+    let lcdc = mem.ppu.lcdc;
+    let (wx, wy) = (mem.ppu.wx as usize, mem.ppu.wy as usize);
+    if lcdc&0x20 != 0 && wx <= 166 && wy <= 143 {
+        let left = wx.saturating_sub(7);
+        outline(&mut rgb, (lcdc&0x40 != 0) as usize, 0, 0, 160 - left, 144 - wy, WINDOW);
+    }
+    outline(&mut rgb, (lcdc&0x08 != 0) as usize, mem.ppu.scx as usize, mem.ppu.scy as usize, 160, 144, VIEWPORT);
+
+    (rgb, WIDTH, SIZE)
+}
+
+pub fn panel(mem: &Memory, mouse: Option<(usize, usize)>) -> Panel {
+    let (rgb, width, height) = render(mem);
+    let lcdc = mem.ppu.lcdc;
+    let mut text = vec![
+        format!("BG {:04X} WIN {:04X} data {:04X}",
+            if lcdc&0x08 != 0 { 0x9C00 } else { 0x9800 },
+            if lcdc&0x40 != 0 { 0x9C00 } else { 0x9800 },
+            if lcdc&0x10 != 0 { 0x8000 } else { 0x8800 }),
+        format!("SCX {} SCY {} WX {} WY {}{}", mem.ppu.scx, mem.ppu.scy, mem.ppu.wx, mem.ppu.wy,
+            if lcdc&0x20 != 0 { "" } else { " (window off)" }),
+    ];
+
+    if let Some((x, y)) = mouse {
+        let map = x / (SIZE + GAP);
+        let x = x % (SIZE + GAP);
+        if x < SIZE && y < SIZE {
+            let (tx, ty) = (x / 8, y / 8);
+            let offset = 0x1800 + map*0x400 + ty*32 + tx;
+            let tile = mem.vram[offset];
+            let attr = attributes(mem, offset);
+            let bank = attr.map_or(0, |a| a.vram_bank as usize);
+
+            text.push(format!("{:04X} ({}, {}) tile {:02X} at {}:{:04X}",
+                0x8000 + offset, tx, ty, tile, bank, 0x8000 + tile_data(mem, tile, 0)));
+            if let Some(a) = attr {
+                text.push(format!("attr {:02X} pal {}{}{}{}", mem.vram[0x2000 + offset], a.palette,
+                    if a.x_flip { " xflip" } else { "" },
+                    if a.y_flip { " yflip" } else { "" },
+                    if a.priority { " priority" } else { "" }));
+            }
+        }
+    }
+    Panel { rgb, width, height, text }
+}
@@ -0,0 +1,81 @@
+use crate::emulator::{Memory, MODE};
+use crate::emulator::ppu::{compose_two_bytes, Pixel_palette};
+use super::{Panel, palette_name};
+
+// Tile data at 0x8000-0x97FF, 16x24 tiles per VRAM bank with the CGB bank 1 to the right
+
+const BANK_WIDTH: usize = 16*8;
+const HEIGHT: usize = 24*8;
+const GAP: usize = 4;
+
+pub struct TileViewer {
+    pub palette: Pixel_palette,
+}
+
+impl TileViewer {
+    pub fn new() -> TileViewer {
+        TileViewer {
+            palette: Pixel_palette::BG,
+        }
+    }
+
+    // P cycles through the palettes of the current mode
+    pub fn next_palette(&mut self, mode: MODE) {
+        let all: Vec<Pixel_palette> = if mode == MODE::CGB {
+            (0 .. 8).map(Pixel_palette::CGB_BG).chain((0 .. 8).map(Pixel_palette::CGB_OBJ)).collect()
+        } else {
+            vec![Pixel_palette::BG, Pixel_palette::OBP0, Pixel_palette::OBP1]
+        };
+        let i = all.iter().position(|p| *p == self.palette).map_or(0, |i| (i + 1) % all.len());
+        self.palette = all[i];
+    }
+
+    pub fn panel(&self, mem: &Memory, mouse: Option<(usize, usize)>) -> Panel {
+        let (rgb, width, height) = render(mem, self.palette);
+        let mut text = vec![format!("{} (P)", palette_name(self.palette))];
+
+        if let Some((bank, tile)) = mouse.and_then(|(x, y)| tile_at(x, y, width)) {
+            let addr = 0x8000 + tile*16;
+            let index = if tile < 0x100 { format!("{:02X}", tile) } else { format!("{:02X} (8800 mode)", tile&0xFF) };
+            text.push(format!("{}:{:04X} tile {}", bank, addr, index));
+        }
+        Panel { rgb, width, height, text }
+    }
+}
+
+fn banks(mem: &Memory) -> usize {
+    if mem.ppu.gb_mode == MODE::CGB { 2 } else { 1 }
+}
+
+// (bank, tile 0-0x17F) at a picture position
+fn tile_at(x: usize, y: usize, width: usize) -> Option<(usize, usize)> {
+    let bank = x / (BANK_WIDTH + GAP);
+    let x = x % (BANK_WIDTH + GAP);
+    if x >= BANK_WIDTH || y >= HEIGHT || (bank+1)*BANK_WIDTH > width {
+        return None
+    }
+    Some((bank, (y / 8) * 16 + x / 8))
+}
+
+pub fn render(mem: &Memory, palette: Pixel_palette) -> (Vec<u8>, usize, usize) {
+    let colors = mem.ppu.palette_rgb(palette);
+    let width = banks(mem) * (BANK_WIDTH + GAP) - GAP;
+    let mut rgb = vec![0x40; width * HEIGHT * 3];
+
+    for bank in 0 .. banks(mem) {
+        for tile in 0 .. 0x180 {
+            for row in 0 .. 8 {
+                let addr = bank*0x2000 + tile*16 + row*2;
+                let pixels = compose_two_bytes(mem.vram[addr], mem.vram[addr+1]);
+                let x = bank*(BANK_WIDTH + GAP) + (tile%16)*8;
+                let y = (tile/16)*8 + row;
+
+                for (i, p) in pixels.iter().enumerate() {
+                    let pos = (y*width + x + i)*3;
+                    rgb[pos .. pos+3].copy_from_slice(&colors[*p as usize]);
+                }
+            }
+        }
+    }
+    (rgb, width, HEIGHT)
+}
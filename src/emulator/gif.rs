@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Animated GIF89a writer. Every frame gets its own color table, exact when the frame has
+// at most 256 colors, a 6x7x6 color cube otherwise.
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+// variable width codes up to 12 bits, the table is cleared once full
+fn lzw(indices: &[u8], min_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_size;
+    let mut w = BitWriter { out: vec![], acc: 0, bits: 0 };
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = min_size + 1;
+    let mut max_code = clear + 1;
+
+    w.write(clear, size);
+    let mut cur = match indices.first() {
+        Some(i) => *i as u16,
+        None => return w.finish()
+    };
+
+    for &k in indices[1 ..].iter() {
+        if let Some(&code) = dict.get(&(cur, k)) {
+            cur = code;
+            continue;
+        }
+
+        w.write(cur, size);
+        max_code += 1;
+        dict.insert((cur, k), max_code);
+        if max_code >= 1 << size {
+            size += 1;
+        }
+        if max_code == 4095 {
+            w.write(clear, size);
+            dict.clear();
+            size = min_size + 1;
+            max_code = clear + 1;
+        }
+        cur = k as u16;
+    }
+
+    w.write(cur, size);
+    if max_code + 1 >= 1 << size && size < 12 {  // the decoder adds an entry for the last code too
+        size += 1;
+    }
+    w.write(clear, size);
+    w.write(clear + 1, min_size + 1);
+    w.finish()
+}
+
+// (color table, index per pixel)
+fn quantize(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut table: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = vec![];
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+
+    for p in rgb.chunks(3) {
+        let c = [p[0], p[1], p[2]];
+        let i = match table.get(&c) {
+            Some(i) => *i,
+            None if palette.len() < 256 => {
+                table.insert(c, palette.len() as u8);
+                palette.push(c);
+                (palette.len() - 1) as u8
+            },
+            None => return cube(rgb)
+        };
+        indices.push(i);
+    }
+    (palette, indices)
+}
+
+fn cube(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    const LEVELS: [usize; 3] = [6, 7, 6];
+
+    let palette = (0 .. 6*7*6)
+        .map(|i| {
+            let (r, g, b) = (i / 42, (i / 6) % 7, i % 6);
+            [(r * 255 / 5) as u8, (g * 255 / 6) as u8, (b * 255 / 5) as u8]
+        })
+        .collect();
+    let level = |v: u8, n: usize| (v as usize * (n-1) + 127) / 255;
+    let indices = rgb.chunks(3)
+        .map(|p| (level(p[0], LEVELS[0]) * 42 + level(p[1], LEVELS[1]) * 6 + level(p[2], LEVELS[2])) as u8)
+        .collect();
+    (palette, indices)
+}
+
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(mut out: W, width: u16, height: u16) -> io::Result<GifWriter<W>> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0x70, 0, 0])?;  // no global color table, 8 bit color resolution
+
+        // NETSCAPE2.0 application extension, loop forever
+        out.write_all(&[0x21, 0xFF, 11])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[3, 1, 0, 0, 0])?;
+
+        Ok(GifWriter { out, width, height })
+    }
+
+    // rgb frame of width*height pixels, delay in 1/100 s
+    pub fn frame(&mut self, rgb: &[u8], delay: u16) -> io::Result<()> {
+        let (palette, indices) = quantize(rgb);
+
+        // graphic control extension
+        self.out.write_all(&[0x21, 0xF9, 4, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        // image descriptor with a 256 entry local color table
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x87])?;
+        for i in 0 .. 256 {
+            self.out.write_all(&palette.get(i).copied().unwrap_or([0; 3]))?;
+        }
+
+        self.out.write_all(&[8])?;
+        for block in lzw(&indices, 8).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -1,10 +1,29 @@
 #![allow(non_camel_case_types)]
 
+use crate::emulator::state::State;
+
 pub trait MemoryBankController {
     fn read_rom(&mut self, addr: u16) -> u8;
     fn write_rom(&mut self, addr: u16, val: u8);
     fn read_ram(&mut self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, val: u8);
+    fn ram(&mut self) -> &mut [u8] { &mut [] }  // whole cartridge RAM, all banks
+    fn rom(&mut self) -> &mut [u8];  // whole ROM image, all banks
+    fn rom_data(&self) -> &[u8];
+    fn ram_data(&self) -> &[u8] { &[] }
+    // ROM image offset mapped at 0x0000-0x7FFF and cartridge RAM offset at 0xA000 + addr (None while disabled)
+    fn rom_offset(&self, addr: u16) -> usize { addr as usize }
+    fn ram_offset(&self, _addr: u16) -> Option<usize> { None }
+
+    // reads without side effects for debuggers, RTC registers read as 0xFF
+    fn peek_rom(&self, addr: u16) -> u8 {
+        self.rom_data().get(self.rom_offset(addr)).copied().unwrap_or(0xFF)
+    }
+    fn peek_ram(&self, addr: u16) -> u8 {
+        self.ram_offset(addr).and_then(|offset| self.ram_data().get(offset).copied()).unwrap_or(0xFF)
+    }
+    fn save_state(&self, _s: &mut State) {}
+    fn load_state(&mut self, _s: &mut State) {}
 }
 
 fn rom_size(val: u8) -> Result<usize, &'static str> {
@@ -56,6 +75,8 @@ impl MemoryBankController for dummyMBC {
     fn write_rom(&mut self, _addr: u16, _val: u8) {}
     fn read_ram(&mut self, _addr: u16) -> u8 { 0xFF }
     fn write_ram(&mut self, _addr: u16, _val: u8) {}
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
 }
 
 
@@ -76,6 +97,8 @@ impl MemoryBankController for noMBC {
     fn write_rom(&mut self, _addr: u16, _val: u8){}
     fn read_ram(&mut self, _addr: u16) -> u8 { 0xFF }
     fn write_ram(&mut self, _addr: u16, _val: u8) {}
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
 }
 
 
@@ -138,6 +161,10 @@ impl MBC1 {
 
 impl MemoryBankController for MBC1 {
     fn read_rom(&mut self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
         match addr {
             0x0000 ..= 0x3FFF => {
                 if self.banking_mode {
@@ -147,9 +174,9 @@ impl MemoryBankController for MBC1 {
                         _ => 0
                     };
 
-                    self.rom[addr as usize + bank as usize*0x4000]
+                    addr as usize + bank as usize*0x4000
                 } else {
-                    self.rom[addr as usize]
+                    addr as usize
                 }
             },
             0x4000 ..= 0x7FFF => {
@@ -159,7 +186,7 @@ impl MemoryBankController for MBC1 {
                     _ => self.bank as usize & 0b00011111
                 };
                 
-                self.rom[(addr as usize&0x3FFF) + 0x4000*bank]
+                (addr as usize&0x3FFF) + 0x4000*bank
             },
             _ => panic!()
         }
@@ -190,6 +217,21 @@ impl MemoryBankController for MBC1 {
     }
 
     fn read_ram(&mut self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram.len() > 0 {
+            if let Some(offset) = self.ram_offset(addr) {
+                self.ram[offset] = val;
+            }
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
         if self.ram_enabled {
             let bank = if self.banking_mode {
                 let b = match self.ram.len() / 0x2000 {
@@ -200,23 +242,27 @@ impl MemoryBankController for MBC1 {
                 b as usize >> 5
             } else { 0 };
 
-            self.ram[addr as usize + bank*0x2000]
-        } else { 0xFF }
+            Some(addr as usize + bank*0x2000)
+        } else { None }
     }
 
-    fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled && self.ram.len() > 0 {
-            let bank = if self.banking_mode {
-                let b = match self.ram.len() / 0x2000 {
-                    2 => self.bank&0x20,
-                    4 => self.bank&0x60,
-                    _ => 0
-                };
-                b as usize >> 5
-            } else { 0 };
+    fn ram(&mut self) -> &mut [u8] { &mut self.ram }
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
+    fn ram_data(&self) -> &[u8] { &self.ram }
 
-            self.ram[addr as usize + bank*0x2000] = val;
-        }
+    fn save_state(&self, s: &mut State) {
+        s.write_bytes(&self.ram);
+        s.write_bool(self.ram_enabled);
+        s.write_u8(self.bank);
+        s.write_bool(self.banking_mode);
+    }
+
+    fn load_state(&mut self, s: &mut State) {
+        s.read_bytes(&mut self.ram);
+        self.ram_enabled = s.read_bool();
+        self.bank = s.read_u8();
+        self.banking_mode = s.read_bool();
     }
 }
 
@@ -261,13 +307,13 @@ impl MBC2 {
 
 impl MemoryBankController for MBC2 {
     fn read_rom(&mut self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
         match addr {
-            0x0000 ..= 0x3FFF => {
-                self.rom[addr as usize]
-            },
-            0x4000 ..= 0x7FFF => {
-                self.rom[(addr&0x3FFF) as usize + self.bank*0x4000]
-            },
+            0x0000 ..= 0x3FFF => addr as usize,
+            0x4000 ..= 0x7FFF => (addr&0x3FFF) as usize + self.bank*0x4000,
             _ => panic!()
         }
     }
@@ -288,18 +334,38 @@ impl MemoryBankController for MBC2 {
     }
 
     fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
-            return self.ram[addr as usize&0x01FF]
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF
         }
-
-        0xFF
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled {
-            self.ram[addr as usize&0x01FF] = val&0xF | 0xF0
+        if let Some(offset) = self.ram_offset(addr) {
+            self.ram[offset] = val&0xF | 0xF0
         }
     }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram_enabled { Some(addr as usize&0x01FF) } else { None }
+    }
+
+    fn ram(&mut self) -> &mut [u8] { &mut self.ram }
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
+    fn ram_data(&self) -> &[u8] { &self.ram }
+
+    fn save_state(&self, s: &mut State) {
+        s.write_bytes(&self.ram);
+        s.write_bool(self.ram_enabled);
+        s.write_u8(self.bank as u8);
+    }
+
+    fn load_state(&mut self, s: &mut State) {
+        s.read_bytes(&mut self.ram);
+        self.ram_enabled = s.read_bool();
+        self.bank = s.read_u8() as usize;
+    }
 }
 
 
@@ -362,13 +428,13 @@ impl MBC3 {
 
 impl MemoryBankController for MBC3 {
     fn read_rom(&mut self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
         match addr {
-            0x0000 ..= 0x3FFF => {
-                self.rom[addr as usize]
-            },
-            0x4000 ..= 0x7FFF => {
-                self.rom[(addr as usize & 0x3FFF) + 0x4000*self.bank as usize]
-            },
+            0x0000 ..= 0x3FFF => addr as usize,
+            0x4000 ..= 0x7FFF => (addr as usize & 0x3FFF) + 0x4000*self.bank as usize,
             _ => panic!()
         }
     }
@@ -425,6 +491,32 @@ impl MemoryBankController for MBC3 {
             }
         }
     }
+
+    // RTC registers aren't memory
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram_enabled && self.ram_bank < 0x4 {
+            Some(addr as usize + self.ram_bank as usize*0x2000)
+        } else { None }
+    }
+
+    fn ram(&mut self) -> &mut [u8] { &mut self.ram }
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
+    fn ram_data(&self) -> &[u8] { &self.ram }
+
+    fn save_state(&self, s: &mut State) {
+        s.write_bytes(&self.ram);
+        s.write_bool(self.ram_enabled);
+        s.write_u8(self.bank);
+        s.write_u8(self.ram_bank);
+    }
+
+    fn load_state(&mut self, s: &mut State) {
+        s.read_bytes(&mut self.ram);
+        self.ram_enabled = s.read_bool();
+        self.bank = s.read_u8();
+        self.ram_bank = s.read_u8();
+    }
 }
 
 
@@ -484,13 +576,13 @@ impl MBC5 {
 impl MemoryBankController for MBC5 {
 
     fn read_rom(&mut self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
         match addr {
-            0x0000 ..= 0x3FFF => {
-                self.rom[addr as usize]
-            },
-            0x4000 ..= 0x7FFF => {
-                self.rom[(addr as usize&0x3FFF) + 0x4000*(self.bank&self.rom_bitmask) as usize]
-            },
+            0x0000 ..= 0x3FFF => addr as usize,
+            0x4000 ..= 0x7FFF => (addr as usize&0x3FFF) + 0x4000*(self.bank&self.rom_bitmask) as usize,
             _ => panic!()
         }
     }
@@ -514,14 +606,40 @@ impl MemoryBankController for MBC5 {
     }
 
     fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
-            self.ram[addr as usize + self.ram_bank as usize*0x2000]
-        } else { 0xFF }
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF
+        }
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled && self.ram.len() > 0 {
-            self.ram[addr as usize + self.ram_bank as usize*0x2000] = val;
+        if self.ram.len() > 0 {
+            if let Some(offset) = self.ram_offset(addr) {
+                self.ram[offset] = val;
+            }
         }
     }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram_enabled { Some(addr as usize + self.ram_bank as usize*0x2000) } else { None }
+    }
+
+    fn ram(&mut self) -> &mut [u8] { &mut self.ram }
+    fn rom(&mut self) -> &mut [u8] { &mut self.rom }
+    fn rom_data(&self) -> &[u8] { &self.rom }
+    fn ram_data(&self) -> &[u8] { &self.ram }
+
+    fn save_state(&self, s: &mut State) {
+        s.write_bytes(&self.ram);
+        s.write_bool(self.ram_enabled);
+        s.write_u16(self.bank);
+        s.write_u8(self.ram_bank);
+    }
+
+    fn load_state(&mut self, s: &mut State) {
+        s.read_bytes(&mut self.ram);
+        self.ram_enabled = s.read_bool();
+        self.bank = s.read_u16();
+        self.ram_bank = s.read_u8();
+    }
 }
\ No newline at end of file
@@ -2,10 +2,14 @@
 
 use std::io::prelude::*;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 
-use crate::emulator::{mbc, PPU, APU, MODE, PPU_MODE};
+use crate::emulator::{mbc, patch, archive, PPU, APU, MODE, PPU_MODE};
+use crate::emulator::cheats::CheatEngine;
+use crate::emulator::state::State;
+use crate::emulator::sgb::Sgb;
+use crate::emulator::palettes::{self, CompatPalette};
 
 const TIMA_SPEED: [u16; 4] = [512, 8, 32, 128];
 
@@ -14,6 +18,11 @@ pub struct Cartridge {
     pub bootrom: Vec<u8>,
     pub bootrom_enable: bool,
     pub title: String,
+    pub checksum: u16,  // global checksum 0x14E-0x14F
+    pub crc: u32,  // crc32 of the whole rom image after patching
+    pub path: PathBuf,  // file the rom was loaded from, the archive rather than the entry in it, patches sit next to it
+    pub sgb_support: bool,  // 0x146 == 0x03 with the new licensee code
+    pub compat_hash: Option<(u8, u8)>,  // title checksum and 4th letter, CGB boot rom palette lookup for Nintendo games
     pub gb_cart_type: MODE
 }
 
@@ -24,10 +33,20 @@ impl Cartridge {
             bootrom: vec![],
             bootrom_enable: false,
             title: String::new(),
+            checksum: 0,
+            crc: 0,
+            path: PathBuf::new(),
+            sgb_support: false,
+            compat_hash: None,
             gb_cart_type: MODE::DMG
         }
     }
 
+    pub fn wrap_rom<F>(&mut self, f: F) where F: FnOnce(Box<dyn mbc::MemoryBankController>) -> Box<dyn mbc::MemoryBankController> {
+        let rom = std::mem::replace(&mut self.rom, mbc::dummyMBC::new(vec![]));
+        self.rom = f(rom);
+    }
+
     #[inline]
     fn read_rom(&mut self, addr: u16) -> u8 {
         self.rom.read_rom(addr)
@@ -48,6 +67,19 @@ impl Cartridge {
         self.rom.write_ram(addr, val)
     }
 
+    pub fn ram(&mut self) -> &mut [u8] {
+        self.rom.ram()
+    }
+
+    // the controller, for reads that shouldn't go through read_rom/read_ram
+    pub fn mbc(&self) -> &dyn mbc::MemoryBankController {
+        self.rom.as_ref()
+    }
+
+    pub fn rom(&mut self) -> &mut [u8] {
+        self.rom.rom()
+    }
+
     pub fn load_bootrom(&mut self, p: &Path) -> Result<MODE, Box<dyn Error>> {
         let mut file = File::open(p)?;
         let mut data: Vec<u8> = vec![];
@@ -70,11 +102,28 @@ impl Cartridge {
         self.rom = mbc::dummyMBC::new(v)
     }
 
-    pub fn load_from_file(&mut self, p: &Path) -> Result<MODE, Box<dyn Error>> {
+    pub fn load_from_file(&mut self, p: &Path, patches: &[PathBuf]) -> Result<MODE, Box<dyn Error>> {
         let mut file = File::open(p)?;
         let mut data: Vec<u8> = vec![];
         file.read_to_end(&mut data)?;
+        data = archive::extract_rom(data)?;
+        self.path = p.to_path_buf();
+
+        // patches next to the rom are applied first, then the ones given explicitly
+        let mut all: Vec<PathBuf> = ["ips", "bps", "ups"].iter()
+            .map(|ext| self.path.with_extension(ext))
+            .filter(|p| p.exists())
+            .collect();
+        all.extend_from_slice(patches);
+
+        for patch_path in all.iter() {
+            let patch_data = std::fs::read(patch_path)?;
+            data = patch::apply(data, &patch_data)
+                .map_err(|e| format!("{}: {}", patch_path.display(), e))?;
+            println!("Applied patch {}", patch_path.display());
+        }
 
+        self.crc = patch::crc32(&data);
         let mode = self.interprete_header(data)?;
         self.gb_cart_type = mode;
 
@@ -89,6 +138,11 @@ impl Cartridge {
 
             let cgb_mode = data[0x143];
             self.title = Cartridge::get_title(&data);
+            self.checksum = ((data[0x14E] as u16) << 8) | data[0x14F] as u16;
+            self.sgb_support = data[0x146] == 0x03 && data[0x14B] == 0x33;
+            self.compat_hash = if data[0x14B] == 0x01 || (data[0x14B] == 0x33 && &data[0x144 ..= 0x145] == b"01") {
+                Some((data[0x134 ..= 0x143].iter().fold(0u8, |acc, b| acc.wrapping_add(*b)), data[0x137]))
+            } else { None };
             match data[0x147] {
                 0x00 => {
                     self.rom = mbc::noMBC::new(data);
@@ -141,13 +195,16 @@ impl Cartridge {
 pub struct Memory {
     pub cart: Cartridge,  // ROM -> 0x0000-0x7FFF 32kB, RAM -> 0xA000-0xBFFF 8kB
     pub ppu: PPU,
-    apu: APU,
+    pub apu: APU,
     pub mode: MODE,
-
-    vram: [u8; 16*1024],  // 0x8000 - 0x9FFF 16kB (2 banks in cgb)
-    ram: [u8; 32*1024], // 0xC000 - 0xDFFF 32kB (8 banks in cgb) + echo at 0xE000 - 0xFDFF
-    OAM: [u8; 160],  // 0xFE00 - 0xFE9F sprite attribute memory
-    hram: [u8; 127],  // 0xFF80 - 0xFFFE high ram
+    pub cheats: CheatEngine,
+    pub frame_done: bool,  // set on VBLANK or once a frame with the LCD off, cleared by whoever consumes the frame
+    compat_palette: Option<CompatPalette>,  // DMG cartridge on CGB hardware, applied once the boot rom is done
+
+    pub vram: [u8; 16*1024],  // 0x8000 - 0x9FFF 16kB (2 banks in cgb)
+    pub ram: [u8; 32*1024], // 0xC000 - 0xDFFF 32kB (8 banks in cgb) + echo at 0xE000 - 0xFDFF
+    pub OAM: [u8; 160],  // 0xFE00 - 0xFE9F sprite attribute memory
+    pub hram: [u8; 127],  // 0xFF80 - 0xFFFE high ram
     pub IF: u8,  // interrupt flag 0xFF0F
     pub IER: u8,  // interrupt enable register 0xFFFF
     vram_bank: u8,
@@ -172,6 +229,8 @@ pub struct Memory {
     serial_count_interrupt: u8,
 
     input_select: u8,
+
+    pub io_written: [bool; 0x100],  // FF00-FFFF written since the debugger last looked
 }
 
 impl Memory {
@@ -184,6 +243,9 @@ impl Memory {
             ppu: ppu,
             apu: apu,
             mode: MODE::DMG,
+            cheats: CheatEngine::new(),
+            frame_done: false,
+            compat_palette: None,
 
             vram: [0; 16*1024],
             ram: [0; 32*1024],
@@ -212,6 +274,8 @@ impl Memory {
             serial_count_interrupt: 0,
 
             input_select: 0,
+
+            io_written: [false; 0x100],
         }
     }
 
@@ -221,30 +285,129 @@ impl Memory {
         Ok(())
     }
 
-    pub fn load_rom(&mut self, p: &Path) -> Result<(), Box<dyn Error>> {
-        self.mode = self.cart.load_from_file(p)?;
+    pub fn load_rom(&mut self, p: &Path, patches: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        self.mode = self.cart.load_from_file(p, patches)?;
         self.ppu.gb_mode = self.mode;
+
+        if let Some(p) = palettes::load_dmg(&self.cart.title)? {
+            self.ppu.set_dmg_palette(&p);
+        }
+
+        if let Err(e) = self.cheats.load(&self.cart.title, self.cart.checksum) {
+            println!("Couldn't load cheats: {}", e);
+        }
+        let cheats = &self.cheats;
+        self.cart.wrap_rom(|rom| cheats.wrap(rom));
+        Ok(())
+    }
+
+    pub fn enable_sgb(&mut self) -> Result<(), &'static str> {
+        if !self.cart.sgb_support {
+            return Err("Cartridge doesn't support SGB")
+        }
+        if self.cart.bootrom_enable && self.mode == MODE::CGB {
+            return Err("SGB mode needs a DMG bootrom or --no-bootrom")
+        }
+
+        self.cart.gb_cart_type = MODE::Sgb;
+        self.mode = MODE::Sgb;
+        self.ppu.gb_mode = MODE::Sgb;
+        self.ppu.sgb = Some(Sgb::new());
+        self.ppu.d.enable_sgb();
         Ok(())
     }
 
+    // applies a palettes::parse_dmg spec and remembers it for this title
+    pub fn set_dmg_palette(&mut self, spec: &str) -> Result<(), Box<dyn Error>> {
+        let p = palettes::parse_dmg(spec)?;
+        self.ppu.set_dmg_palette(&p);
+        palettes::save_dmg(&self.cart.title, spec)
+    }
+
+    // runs a DMG cartridge the way a CGB would, combo is one of the boot rom button combos
+    pub fn set_cgb_compat(&mut self, combo: Option<&str>) -> Result<(), Box<dyn Error>> {
+        if self.cart.gb_cart_type != MODE::DMG {
+            return Ok(())  // colors itself
+        }
+
+        let palette = match combo {
+            Some(c) => palettes::combo(c).ok_or_else(|| {
+                format!("Unknown palette {}, one of: {}", c, palettes::combo_names().collect::<Vec<_>>().join(" "))
+            })?,
+            None if self.cart.bootrom_enable && self.mode == MODE::CGB => return Ok(()),  // the boot rom picks it
+            None => palettes::for_title(self.cart.compat_hash)
+        };
+        self.compat_palette = Some(palette);
+        if !self.cart.bootrom_enable {
+            self.ppu.set_compat_palette(&palette);
+        }
+        Ok(())
+    }
+
+    fn update_cheat_keys(&mut self) {  // 1-9 toggle cheats
+        use raylib::consts::KeyboardKey::*;
+        const KEYS: [raylib::consts::KeyboardKey; 9] = [KEY_ONE, KEY_TWO, KEY_THREE, KEY_FOUR, KEY_FIVE, KEY_SIX, KEY_SEVEN, KEY_EIGHT, KEY_NINE];
+
+        let mut changed = false;
+        for (i, key) in KEYS.iter().enumerate() {
+            if self.ppu.d.handle.is_key_pressed(*key) && i < self.cheats.cheats.len() {
+                self.cheats.toggle(i);
+                let c = &self.cheats.cheats[i];
+                println!("Cheat {} {} {}", c.code, c.name, if c.enabled { "enabled" } else { "disabled" });
+                changed = true;
+            }
+        }
+
+        if changed {
+            if let Err(e) = self.cheats.save() {
+                println!("Couldn't save cheats: {}", e);
+            }
+        }
+    }
+
+    fn apply_game_shark(&mut self) {
+        let codes: Vec<(u8, u16, u8)> = self.cheats.game_shark().collect();
+
+        for (bank, addr, val) in codes {
+            match (bank, addr) {
+                (0x80 ..= 0x87, 0xD000 ..= 0xDFFF) | (0x90 ..= 0x97, 0xD000 ..= 0xDFFF) => {
+                    let _ = self.poke_banked((bank as usize&0x7).max(1), addr, val);
+                },
+                (0x80 ..= 0x8F, 0xA000 ..= 0xBFFF) => {
+                    let _ = self.poke_banked(bank as usize&0xF, addr, val);
+                },
+                _ => { let _ = self.poke(addr, val); }
+            }
+        }
+    }
+
+    fn bootrom_mapped(&self, addr: u16) -> bool {
+        self.cart.bootrom_enable && match addr {
+            0x0000 ..= 0x00FF => true,
+            0x0201 ..= 0x08FF => self.mode == MODE::CGB,
+            _ => false
+        }
+    }
+
     #[inline]
     pub fn read(&mut self, addr: u16) -> u8 {
-        if self.cart.bootrom_enable {
-            match addr {
-                0x0000 ..= 0x00FF => {
-                    return self.cart.bootrom[addr as usize]
-                }
-                0x0201 ..= 0x08FF if self.mode == MODE::CGB => {
-                    return self.cart.bootrom[addr as usize]
-                }
-                _ => ()
-            }
+        match addr {
+            0x0000 ..= 0x7FFF if !self.bootrom_mapped(addr) => self.cart.read_rom(addr),
+            0xA000 ..= 0xBFFF => self.cart.read_ram(addr-0xa000),
+            _ => self.peek(addr)
+        }
+    }
+
+    // what read() returns, without side effects, for debuggers and tools
+    pub fn peek(&self, addr: u16) -> u8 {
+        if self.bootrom_mapped(addr) {
+            return self.cart.bootrom[addr as usize]
         }
 
         match addr {
-            0x0000 ..= 0x7FFF => self.cart.read_rom(addr),
+            0x0000 ..= 0x7FFF => self.cart.mbc().peek_rom(addr),
             0x8000 ..= 0x9FFF => self.vram[(addr as usize&0x1FFF) + self.vram_bank as usize*0x2000],
-            0xA000 ..= 0xBFFF => self.cart.read_ram(addr-0xa000),
+            0xA000 ..= 0xBFFF => self.cart.mbc().peek_ram(addr-0xa000),
             0xC000 ..= 0xCFFF => self.ram[(addr&0xFFF) as usize],
             0xD000 ..= 0xDFFF => self.ram[(addr as usize&0xFFF) + self.ram_bank as usize*0x1000],
             0xE000 ..= 0xFDFF => self.ram[(addr-0xe000) as usize],
@@ -252,11 +415,13 @@ impl Memory {
 
             // Memory mapped io
             0xFF00 => {
-                match self.input_select&0x30 {
-                    0x00 => 0xF,
-                    0x10 => self.ppu.in_button | self.input_select,
-                    0x20 => self.ppu.in_direction | self.input_select,
-                    0x30 => 0xFF,
+                match (self.input_select&0x30, &self.ppu.sgb) {
+                    (0x00, _) => 0xF,
+                    (0x10 | 0x20, Some(sgb)) if !sgb.is_player_one() => 0xF | self.input_select,  // other SGB players idle
+                    (0x10, _) => self.ppu.in_button | self.input_select,
+                    (0x20, _) => self.ppu.in_direction | self.input_select,
+                    (0x30, Some(sgb)) => 0xF0 | sgb.joypad_id(),
+                    (0x30, None) => 0xFF,
                     _ => panic!()
                 }
             },
@@ -283,8 +448,82 @@ impl Memory {
         }
     }
 
+    // addr inside a specific bank regardless of the bank registers: ROM (0x0000-0x7FFF, 16kB banks),
+    // VRAM (0x8000), cartridge RAM (0xA000) or WRAM (0xD000). Unbanked areas are the same as peek()
+    pub fn peek_banked(&self, bank: usize, addr: u16) -> u8 {
+        let a = addr as usize;
+        let byte = match addr {
+            0x0000 ..= 0x7FFF => self.cart.mbc().rom_data().get(bank*0x4000 + (a&0x3FFF)),
+            0x8000 ..= 0x9FFF => self.vram.get(bank*0x2000 + (a&0x1FFF)),
+            0xA000 ..= 0xBFFF => self.cart.mbc().ram_data().get(bank*0x2000 + (a&0x1FFF)),
+            0xD000 ..= 0xDFFF => self.ram.get(bank*0x1000 + (a&0xFFF)),
+            _ => return self.peek(addr)
+        };
+        byte.copied().unwrap_or(0xFF)
+    }
+
+    // what write() stores, without side effects: ROM writes patch the image instead of reaching the MBC
+    // and I/O registers don't start transfers, DMA or sound, or reset DIV
+    pub fn poke(&mut self, addr: u16, val: u8) -> Result<(), &'static str> {
+        let a = addr as usize;
+        match addr {
+            0x0000 ..= 0x7FFF => {
+                let offset = self.cart.mbc().rom_offset(addr);
+                *self.cart.rom().get_mut(offset).ok_or("outside the ROM")? = val;
+            },
+            0x8000 ..= 0x9FFF => self.vram[(a&0x1FFF) + self.vram_bank as usize*0x2000] = val,
+            0xA000 ..= 0xBFFF => {
+                let offset = self.cart.mbc().ram_offset(addr-0xA000).ok_or("cartridge RAM is disabled")?;
+                *self.cart.ram().get_mut(offset).ok_or("no cartridge RAM")? = val;
+            },
+            0xC000 ..= 0xCFFF => self.ram[a&0xFFF] = val,
+            0xD000 ..= 0xDFFF => self.ram[(a&0xFFF) + self.ram_bank as usize*0x1000] = val,
+            0xE000 ..= 0xFDFF => self.ram[a-0xE000] = val,
+            0xFE00 ..= 0xFE9F => self.OAM[a-0xFE00] = val,
+            0xFF80 ..= 0xFFFE => self.hram[a-0xFF80] = val,
+
+            // registers take the value as is, nothing is started or reset
+            0xFF00 => self.input_select = val&0x30,
+            0xFF01 => self.serial_transfer = val,
+            0xFF02 => self.serial_control = 0b01111110 | val,
+            0xFF04 => self.DIV = (val as u16) << 8,
+            0xFF05 => self.TIMA = val,
+            0xFF06 => self.TMA = val,
+            0xFF07 => self.TAC = 0b11111000 | val,
+            0xFF0F => self.IF = 0b11100000 | val,
+            0xFF10 ..= 0xFF3F => self.apu.poke(addr, val)?,
+            0xFF40 ..= 0xFF4B => self.ppu.poke(addr, val)?,
+            0xFF4F if self.mode == MODE::CGB => self.vram_bank = val&0x1,
+            0xFF51 ..= 0xFF54 => self.write(addr, val),  // only latch the HDMA addresses
+            0xFF55 if self.mode == MODE::CGB => self.hdma5 = val,
+            0xFF68 ..= 0xFF6C if self.mode == MODE::CGB => self.ppu.poke(addr, val)?,
+            0xFF70 if self.mode == MODE::CGB => self.ram_bank = (val&0x07).max(1),
+            0xFFFF => self.IER = 0b11100000 | val,
+            0xFF50 => return Err("the boot ROM can only be unmapped by a write"),
+            _ => return Err("nothing to poke at this address")
+        }
+        Ok(())
+    }
+
+    pub fn poke_banked(&mut self, bank: usize, addr: u16, val: u8) -> Result<(), &'static str> {
+        let a = addr as usize;
+        let byte = match addr {
+            0x0000 ..= 0x7FFF => self.cart.rom().get_mut(bank*0x4000 + (a&0x3FFF)),
+            0x8000 ..= 0x9FFF => self.vram.get_mut(bank*0x2000 + (a&0x1FFF)),
+            0xA000 ..= 0xBFFF => self.cart.ram().get_mut(bank*0x2000 + (a&0x1FFF)),
+            0xD000 ..= 0xDFFF => self.ram.get_mut(bank*0x1000 + (a&0xFFF)),
+            _ => return self.poke(addr, val)
+        };
+        *byte.ok_or("bank out of range")? = val;
+        Ok(())
+    }
+
     #[inline]
     pub fn write(&mut self, addr: u16, mut val: u8) {
+        if addr >= 0xFF00 {
+            self.io_written[addr as usize&0xFF] = true;
+        }
+
         match addr {
             0x0000 ..= 0x7FFF => self.cart.write_rom(addr, val),
             0x8000 ..= 0x9FFF => self.vram[(addr as usize&0x1FFF) + self.vram_bank as usize * 0x2000] = val,
@@ -296,7 +535,10 @@ impl Memory {
 
             // Memory mapped io
             0xFF00 => {
-                self.input_select = val&0x30
+                self.input_select = val&0x30;
+                if let Some(sgb) = &mut self.ppu.sgb {
+                    sgb.write_p1(val);
+                }
             },
             0xFF01 => {
                 self.serial_transfer = val;
@@ -337,13 +579,17 @@ impl Memory {
                 }
             }
             0xFF40 ..= 0xFF4B => {
-                self.ppu.write(addr, val)
+                self.ppu.write_latched(addr, val)
             },
             0xFF4F if self.mode == MODE::CGB => {
                 self.vram_bank = val&0x1;
             },
-            0xFF50 => {
-                self.cart.bootrom = vec![];
+            0xFF50 => {  // bootrom is kept around so rewinding into it still works
+                if let Some(p) = self.compat_palette {
+                    self.ppu.set_compat_palette(&p);
+                } else if self.mode == MODE::CGB && self.cart.gb_cart_type == MODE::DMG {
+                    self.ppu.compat = true;  // cgb boot rom already loaded the palettes
+                }
                 self.cart.bootrom_enable = false;
                 self.mode = self.cart.gb_cart_type;
                 self.ppu.gb_mode = self.cart.gb_cart_type;
@@ -396,11 +642,88 @@ impl Memory {
         }
     }
 
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.mode as u8);
+        s.write_bool(self.cart.bootrom_enable);
+        self.cart.rom.save_state(s);
+        self.ppu.save_state(s);
+        self.apu.save_state(s);
+
+        s.write_bytes(&self.vram);
+        s.write_bytes(&self.ram);
+        s.write_bytes(&self.OAM);
+        s.write_bytes(&self.hram);
+        s.write_u8(self.IF);
+        s.write_u8(self.IER);
+        s.write_u8(self.vram_bank);
+        s.write_u8(self.ram_bank);
+
+        s.write_u16(self.vdma_src);
+        s.write_u16(self.vdma_dst);
+        s.write_u8(self.hdma5);
+        s.write_bool(self.hdma_active);
+        s.write_u8(self.hdma_length);
+
+        s.write_u16(self.DIV);
+        s.write_u8(self.TIMA);
+        s.write_u8(self.TMA);
+        s.write_u8(self.TAC);
+        s.write_u8(self.tima_schedule as u8);
+        s.write_u16(self.last_div);
+
+        s.write_u8(self.serial_control);
+        s.write_u8(self.serial_transfer);
+        s.write_u8(self.serial_count_interrupt);
+        s.write_u8(self.input_select);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.mode = MODE::from(s.read_u8());
+        self.cart.bootrom_enable = s.read_bool() && !self.cart.bootrom.is_empty();
+        self.cart.rom.load_state(s);
+        self.ppu.load_state(s);
+        self.apu.load_state(s);
+
+        s.read_bytes(&mut self.vram);
+        s.read_bytes(&mut self.ram);
+        s.read_bytes(&mut self.OAM);
+        s.read_bytes(&mut self.hram);
+        self.IF = s.read_u8();
+        self.IER = s.read_u8();
+        self.vram_bank = s.read_u8();
+        self.ram_bank = s.read_u8();
+
+        self.vdma_src = s.read_u16();
+        self.vdma_dst = s.read_u16();
+        self.hdma5 = s.read_u8();
+        self.hdma_active = s.read_bool();
+        self.hdma_length = s.read_u8();
+
+        self.DIV = s.read_u16();
+        self.TIMA = s.read_u8();
+        self.TMA = s.read_u8();
+        self.TAC = s.read_u8();
+        self.tima_schedule = s.read_u8() as i8;
+        self.last_div = s.read_u16();
+
+        self.serial_control = s.read_u8();
+        self.serial_transfer = s.read_u8();
+        self.serial_count_interrupt = s.read_u8();
+        self.input_select = s.read_u8();
+    }
+
     pub fn tick(&mut self) {
         let ppu_mode = self.ppu.mode;
         self.ppu.tick(&mut self.vram, &mut self.OAM, &mut self.IF, &self.input_select);
         self.apu.tick();
 
+        let blank_frame = std::mem::take(&mut self.ppu.blank_frame);
+        if blank_frame || (ppu_mode != self.ppu.mode && self.ppu.mode == PPU_MODE::VBLANK) {
+            self.update_cheat_keys();
+            self.apply_game_shark();
+            self.frame_done = true;
+        }
+
         if self.hdma_active {
             if ppu_mode != self.ppu.mode && self.ppu.mode == PPU_MODE::HBLANK {
                 let mut offset = self.hdma_length as u16 - (self.hdma5 as u16&0x7F);
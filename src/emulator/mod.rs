@@ -4,15 +4,44 @@ mod ppu;
 mod opcodes;
 pub mod mbc;
 pub mod apu;
+pub mod cheats;
+pub mod search;
+pub mod patch;
+pub mod archive;
+pub mod state;
+pub mod rewind;
+pub mod speed;
+pub mod movie;
+pub mod sgb;
+pub mod palettes;
+pub mod color;
+pub mod blend;
+pub mod scaler;
+pub mod gif;
+pub mod record;
+pub mod debug;
+mod console;
 
 pub use cpu::{CPU, Flag};
 pub use memory::{Memory, Cartridge};
 pub use opcodes::{execute, PUSH};
 pub use ppu::{PPU, PPU_MODE};
 pub use apu::APU;
+pub use console::Console;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum MODE {
     DMG,
     CGB,
+    Sgb,  // dmg hardware, colored and framed by the SNES side
+}
+
+impl From<u8> for MODE {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => MODE::CGB,
+            2 => MODE::Sgb,
+            _ => MODE::DMG
+        }
+    }
 }
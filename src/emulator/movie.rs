@@ -0,0 +1,188 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::emulator::Cartridge;
+use crate::emulator::state::State;
+
+// header, then one joypad byte (Draw::poll_joypad layout) per frame
+const MAGIC: &[u8; 8] = b"SPGBMOV1";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub struct MovieHeader {
+    pub version: String,
+    pub rom_crc: u32,
+    pub title: String,
+    pub rtc_seed: u64,  // unix time at start, MBC3 RTC isn't clocked yet so it's only recorded
+    pub frames: u32,
+    pub start_state: Option<Vec<u8>>,  // None - starts at power on
+}
+
+impl MovieHeader {
+    fn write(&self) -> (Vec<u8>, usize) {  // (data, position of the frame count)
+        let mut s = State::new();
+        s.data.extend_from_slice(MAGIC);
+        s.write_bytes(self.version.as_bytes());
+        s.write_u32(self.rom_crc);
+        s.write_bytes(self.title.as_bytes());
+        s.write_u32(self.rtc_seed as u32);
+        s.write_u32((self.rtc_seed >> 32) as u32);
+        let frames_pos = s.data.len();
+        s.write_u32(self.frames);
+        match &self.start_state {
+            Some(state) => {
+                s.write_bool(true);
+                s.write_bytes(state);
+            },
+            None => s.write_bool(false)
+        }
+        (s.data, frames_pos)
+    }
+
+    fn read_fields(s: &mut State) -> Option<MovieHeader> {
+        Some(MovieHeader {
+            version: String::from_utf8_lossy(&s.try_read_vec()?).into_owned(),
+            rom_crc: s.try_read_u32()?,
+            title: String::from_utf8_lossy(&s.try_read_vec()?).into_owned(),
+            rtc_seed: s.try_read_u32()? as u64 | (s.try_read_u32()? as u64) << 32,
+            frames: s.try_read_u32()?,
+            start_state: if s.try_read_u8()? != 0 { Some(s.try_read_vec()?) } else { None },
+        })
+    }
+
+    fn read(data: Vec<u8>) -> Result<(MovieHeader, Vec<u8>), Box<dyn Error>> {
+        if !data.starts_with(MAGIC) {
+            return Err("Not a movie file".into())
+        }
+
+        let mut s = State::from(data);
+        s.read_u32();  // magic, checked above
+        s.read_u32();
+        let header = MovieHeader::read_fields(&mut s).ok_or("Truncated movie")?;
+        let inputs = s.read_rest();
+        Ok((header, inputs))
+    }
+}
+
+enum MovieMode {
+    Idle,
+    Recording { file: File, path: PathBuf, frames_pos: usize, frames: u32 },
+    Playing { inputs: Vec<u8>, pos: usize, expected: u32 },
+}
+
+pub struct Movie {
+    mode: MovieMode,
+}
+
+impl Movie {
+    pub fn new() -> Movie {
+        Movie {
+            mode: MovieMode::Idle,
+        }
+    }
+
+    // recording or playing, the inputs only match the game if nothing rewinds it
+    pub fn is_active(&self) -> bool {
+        !matches!(self.mode, MovieMode::Idle)
+    }
+
+    pub fn record(&mut self, p: &Path, cart: &Cartridge, start_state: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        self.stop();
+
+        let header = MovieHeader {
+            version: VERSION.to_string(),
+            rom_crc: cart.crc,
+            title: cart.title.clone(),
+            rtc_seed: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            frames: 0,
+            start_state,
+        };
+        let (data, frames_pos) = header.write();
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(p)?;
+        file.write_all(&data)?;
+
+        println!("Recording movie to {}", p.display());
+        self.mode = MovieMode::Recording { file, path: p.to_path_buf(), frames_pos, frames: 0 };
+        Ok(())
+    }
+
+    // returns the state to load before the first frame, if the movie doesn't start at power on.
+    // A power on movie can only be played while the machine hasn't run yet
+    pub fn play(&mut self, p: &Path, cart: &Cartridge, power_on: bool) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let (header, inputs) = MovieHeader::read(std::fs::read(p)?)?;
+        if header.rom_crc != cart.crc {
+            return Err(format!("Movie was recorded with a different rom ({} {:08X})", header.title, header.rom_crc).into())
+        }
+        if header.start_state.is_none() && !power_on {
+            return Err("Movie starts at power on, play it with --play".into())
+        }
+        if header.version != VERSION {
+            println!("Movie was recorded with emulator version {}, playback may desync", header.version);
+        }
+        if header.frames as usize != inputs.len() {
+            println!("Movie header says {} frames but has {}, the recording wasn't finished", header.frames, inputs.len());
+        }
+
+        self.stop();
+
+        println!("Playing movie {} ({} frames)", p.display(), header.frames);
+        self.mode = MovieMode::Playing { inputs, pos: 0, expected: header.frames };
+        Ok(header.start_state)
+    }
+
+    pub fn stop(&mut self) {
+        match std::mem::replace(&mut self.mode, MovieMode::Idle) {
+            MovieMode::Recording { mut file, path, frames_pos, frames } => {
+                let r = file.seek(SeekFrom::Start(frames_pos as u64))
+                    .and_then(|_| file.write_all(&frames.to_le_bytes()));
+                match r {
+                    Ok(_) => println!("Recorded {} frames to {}", frames, path.display()),
+                    Err(e) => println!("Couldn't finish movie {}: {}", path.display(), e)
+                }
+            },
+            MovieMode::Playing { pos, expected, .. } => {
+                if pos as u32 == expected {
+                    println!("Movie finished after {} frames", pos);
+                } else {
+                    println!("Movie stopped after {} of {} frames", pos, expected);
+                }
+            },
+            MovieMode::Idle => ()
+        }
+    }
+
+    // called once per frame with the live input, returns the input the frame should use
+    pub fn frame(&mut self, live: u8) -> u8 {
+        match &mut self.mode {
+            MovieMode::Recording { file, frames, .. } => {
+                if let Err(e) = file.write_all(&[live]) {
+                    println!("Movie recording stopped: {}", e);
+                    self.stop();
+                } else {
+                    *frames += 1;
+                }
+                live
+            },
+            MovieMode::Playing { inputs, pos, .. } => {
+                if let Some(input) = inputs.get(*pos).copied() {
+                    *pos += 1;
+                    input
+                } else {
+                    self.stop();
+                    live
+                }
+            },
+            MovieMode::Idle => live
+        }
+    }
+}
+
+impl Drop for Movie {
+    fn drop(&mut self) {
+        if let MovieMode::Recording { .. } = self.mode {
+            self.stop();
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::error::Error;
+use std::fs;
+
+// Palettes the CGB boot rom gives DMG cartridges, as 15-bit colors: bg, obj0, obj1
+
+pub type CompatPalette = [[u16; 4]; 3];
+
+// the boot rom's color table, 4 colors per palette
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+const fn comb(obj0: usize, obj1: usize, bg: usize) -> (usize, usize, usize) {
+    (obj0*4, obj1*4, bg*4)
+}
+
+// (obj0, obj1, bg) offsets into COLORS, the few that aren't multiples of 4 overlap two palettes like in the boot rom
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    comb(4, 4, 29), comb(18, 18, 18), comb(20, 20, 20), comb(24, 24, 24), comb(9, 9, 9),
+    comb(0, 0, 0), comb(27, 27, 27), comb(5, 5, 5), comb(12, 12, 12), comb(26, 26, 26),
+    comb(16, 8, 8), comb(4, 28, 28), comb(4, 2, 2), comb(3, 4, 4), comb(4, 29, 29),
+    comb(28, 4, 28), comb(2, 17, 2), comb(16, 16, 8), comb(4, 4, 7), comb(4, 4, 18),
+    comb(4, 4, 20), comb(19, 19, 9), (15, 15, 44), comb(17, 17, 2), comb(4, 4, 2),
+    comb(4, 4, 3), comb(28, 28, 0), comb(3, 3, 0), comb(0, 0, 1), comb(18, 22, 18),
+    comb(20, 22, 20), comb(24, 22, 24), comb(16, 22, 8), comb(17, 4, 13), (111, 0, 56),
+    (111, 16, 60), comb(19, 22, 9), comb(16, 28, 10), comb(4, 23, 28), comb(17, 22, 2),
+    comb(4, 0, 2), comb(4, 28, 3), comb(28, 3, 0), comb(3, 28, 4), comb(21, 28, 4),
+    comb(3, 28, 0), comb(25, 3, 28), comb(0, 28, 8), comb(4, 3, 28), comb(28, 3, 6),
+    comb(4, 28, 29),
+];
+
+fn combination(i: usize) -> CompatPalette {
+    let (obj0, obj1, bg) = COMBINATIONS[i];
+    let palette = |o: usize| [COLORS[o], COLORS[o+1], COLORS[o+2], COLORS[o+3]];
+    [palette(bg), palette(obj0), palette(obj1)]
+}
+
+// held direction + button while the boot rom logo is shown
+const COMBOS: [(&str, usize); 12] = [
+    ("up", 5),
+    ("up+a", 43),
+    ("up+b", 28),
+    ("down", 8),
+    ("down+a", 3),
+    ("down+b", 49),
+    ("left", 48),
+    ("left+a", 40),
+    ("left+b", 7),
+    ("right", 0),
+    ("right+a", 1),
+    ("right+b", 6),
+];
+
+// (title checksum, 4th title letter for colliding checksums, combination), the boot rom's table
+const TITLES: [(u8, Option<u8>, usize); 94] = [
+    (0x00, None, 0),
+    (0x88, None, 4),  // ALLEY WAY
+    (0x16, None, 5),  // YAKUMAN
+    (0x36, None, 35),  // BASEBALL
+    (0xD1, None, 34),  // TENNIS
+    (0xDB, None, 3),  // TETRIS
+    (0xF2, None, 31),  // QIX
+    (0x3C, None, 15),  // DR.MARIO
+    (0x8C, None, 10),  // RADARMISSION
+    (0x92, None, 5),  // F1RACE
+    (0x3D, None, 19),  // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7),  // X
+    (0xC9, None, 37),  // MARIOLAND2
+    (0x3E, None, 30),  // YOSSY NO COOKIE
+    (0x70, None, 44),  // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31),  // TETRIS FLASH
+    (0x19, None, 20),  // DONKEY KONG
+    (0x35, None, 5),  // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13),  // POKEMON RED
+    (0xAA, None, 14),  // POKEMON GREEN
+    (0x75, None, 5),  // PICROSS 2
+    (0x95, None, 29),  // YOSSY NO PANEPON
+    (0x99, None, 5),  // KIRAKIRA KIDS
+    (0x34, None, 18),  // GAMEBOY GALLERY
+    (0x6F, None, 9),  // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2),  // BALLOON KID
+    (0x97, None, 26),  // KINGOFTHEZOO
+    (0x4B, None, 25),  // DMG FOOTBALL
+    (0x90, None, 25),  // WORLD CUP
+    (0x17, None, 41),  // OTHELLO
+    (0x10, None, 42),  // SUPER RC PRO-AM
+    (0x39, None, 26),  // DYNABLASTER
+    (0xF7, None, 45),  // BOY AND BLOB GB2
+    (0xF6, None, 42),  // MEGAMAN
+    (0xA2, None, 45),  // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38),  // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42),  // LOLO2
+    (0xE0, None, 30),  // YOSHI'S COOKIE
+    (0x8B, None, 41),  // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34),  // TOPRANKINGTENNIS
+    (0x0C, None, 5),  // MANSELL
+    (0x29, None, 42),  // MEGAMAN3
+    (0xE8, None, 6),  // SPACE INVADERS
+    (0xB7, None, 5),  // GAME&WATCH
+    (0x86, None, 33),  // DONKEYKONGLAND95
+    (0x9A, None, 25),  // ASTEROIDS/MISCMD
+    (0x52, None, 42),  // STREET FIGHTER 2
+    (0x01, None, 42),  // DEFENDER/JOUST
+    (0x9D, None, 40),  // KILLERINSTINCT95
+    (0x71, None, 2),  // TETRIS BLAST
+    (0x9C, None, 16),  // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42),  // BA.TOSHINDEN
+    (0x6D, None, 42),  // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0),  // TETRIS PLUS
+    (0x6B, None, 39),  // DONKEYKONGLAND 3
+    (0xB3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22),  // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25),  // GOLF
+    (0xA5, Some(b'A'), 6),  // SOLARSTRIKER
+    (0xC6, Some(b'A'), 32),  // GBWARS
+    (0xD3, Some(b'R'), 12),  // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11),  // POKEMON BLUE
+    (0x18, Some(b'K'), 39),  // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18),  // GAMEBOY GALLERY2
+    (0x6A, Some(b'K'), 39),  // DONKEYKONGLAND 2
+    (0xBF, Some(b' '), 24),
+    (0x0D, Some(b'R'), 31),
+    (0xF4, Some(b'-'), 50),
+    (0xB3, Some(b'U'), 17),
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),
+    (0xA5, Some(b'R'), 27),
+    (0xC6, Some(b' '), 0),
+    (0xD3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41),
+    (0x61, Some(b'A'), 41),
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),
+    (0x6A, Some(b'I'), 19),
+    (0xBF, Some(b'C'), 34),
+    (0x0D, Some(b'E'), 23),
+    (0xF4, Some(b' '), 18),
+    (0xB3, Some(b'R'), 29),
+];
+
+pub fn combo(name: &str) -> Option<CompatPalette> {
+    let name = name.to_lowercase();
+    COMBOS.iter().find(|(n, _)| *n == name).map(|(_, i)| combination(*i))
+}
+
+pub fn combo_names() -> impl Iterator<Item = &'static str> {
+    COMBOS.iter().map(|(n, _)| *n)
+}
+
+// hash is None for non-Nintendo cartridges, those always get the default palette
+pub fn for_title(hash: Option<(u8, u8)>) -> CompatPalette {
+    let index = hash.and_then(|(sum, fourth)| {
+        TITLES.iter()
+            .find(|(s, f, _)| *s == sum && (f.is_none() || *f == Some(fourth)))
+            .map(|(_, _, i)| *i)
+    });
+    combination(index.unwrap_or(0))
+}
+
+// DMG shades as 24-bit rgb, lightest first: bg, obp0, obp1
+
+pub type DmgPalette = [[u32; 4]; 3];
+
+const CONFIG_FILE: &str = "palettes.cfg";
+
+const PRESETS: [(&str, [u32; 4]); 3] = [
+    ("gray", [0xFFFFFF, 0xC8C8C8, 0x828282, 0x000000]),
+    ("pocket", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+    ("green", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+];
+
+pub const DEFAULT_DMG: DmgPalette = [PRESETS[0].1; 3];
+
+// preset name or 4 hex colors "e0f8d0,88c070,346856,081820"
+fn parse_shades(s: &str) -> Result<[u32; 4], String> {
+    if let Some((_, p)) = PRESETS.iter().find(|(n, _)| *n == s.to_lowercase()) {
+        return Ok(*p)
+    }
+
+    let colors = s.split(',')
+        .map(|c| u32::from_str_radix(c.trim().trim_start_matches('#'), 16).ok().filter(|c| *c <= 0xFFFFFF))
+        .collect::<Option<Vec<u32>>>();
+    match colors {
+        Some(c) if c.len() == 4 => Ok([c[0], c[1], c[2], c[3]]),
+        _ => Err(format!("Invalid palette {}, expected {} or 4 hex colors", s,
+            PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join("/")))
+    }
+}
+
+// "bg/obp0/obp1", a single palette is used for all three
+pub fn parse_dmg(spec: &str) -> Result<DmgPalette, String> {
+    let parts = spec.split('/').map(parse_shades).collect::<Result<Vec<_>, _>>()?;
+    match parts.len() {
+        1 => Ok([parts[0]; 3]),
+        3 => Ok([parts[0], parts[1], parts[2]]),
+        _ => Err(format!("Invalid palette {}, expected bg/obp0/obp1", spec))
+    }
+}
+
+// one game per line: "TITLE = spec"
+pub fn load_dmg(title: &str) -> Result<Option<DmgPalette>, Box<dyn Error>> {
+    let title = title.trim();
+    if !std::path::Path::new(CONFIG_FILE).exists() {
+        return Ok(None)
+    }
+
+    for line in fs::read_to_string(CONFIG_FILE)?.lines() {
+        if let Some((t, spec)) = line.rsplit_once('=') {
+            if t.trim() == title {
+                return Ok(Some(parse_dmg(spec.trim())?))
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn save_dmg(title: &str, spec: &str) -> Result<(), Box<dyn Error>> {
+    let title = title.trim();
+    let old = fs::read_to_string(CONFIG_FILE).unwrap_or_default();
+
+    let mut out: String = old.lines()
+        .filter(|l| !matches!(l.rsplit_once('='), Some((t, _)) if t.trim() == title))
+        .map(|l| format!("{}\n", l))
+        .collect();
+    out += &format!("{} = {}\n", title, spec);
+    fs::write(CONFIG_FILE, out)?;
+    Ok(())
+}
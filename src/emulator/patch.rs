@@ -0,0 +1,210 @@
+// IPS, UPS and BPS soft patching
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0 .. 8 {
+            crc = if crc&1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        let b = *self.data.get(self.pos).ok_or("Unexpected end of patch")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.data.len()).ok_or("Unexpected end of patch")?;
+        let bytes = &self.data[self.pos .. end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn be(&mut self, n: usize) -> Result<usize, &'static str> {
+        Ok(self.bytes(n)?.iter().fold(0, |acc, b| (acc << 8) | *b as usize))
+    }
+
+    fn varint(&mut self) -> Result<usize, &'static str> {  // UPS/BPS number encoding
+        let mut val = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.byte()?;
+            val = ((x&0x7F) as usize).checked_mul(shift).and_then(|v| v.checked_add(val)).ok_or("Invalid patch")?;
+            if x&0x80 != 0 { break }
+            shift = shift.checked_mul(0x80).ok_or("Invalid patch")?;
+            val = val.checked_add(shift).ok_or("Invalid patch")?;
+        }
+        Ok(val)
+    }
+}
+
+fn le32(data: &[u8]) -> u32 {
+    data.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u32)
+}
+
+fn check_footer(patch: &[u8]) -> Result<(u32, u32), &'static str> {  // (source crc, target crc)
+    if patch.len() < 12 {
+        return Err("Patch too small")
+    }
+    let footer = &patch[patch.len()-12 ..];
+    if crc32(&patch[.. patch.len()-4]) != le32(&footer[8 ..]) {
+        return Err("Patch checksum mismatch")
+    }
+    Ok((le32(&footer[.. 4]), le32(&footer[4 .. 8])))
+}
+
+fn ips(mut data: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut r = Reader { data: patch, pos: 5 };
+
+    loop {
+        if r.bytes(3)? == b"EOF" {
+            if r.pos + 3 <= patch.len() {  // truncation extension
+                let size = r.be(3)?;
+                data.truncate(size);
+            }
+            return Ok(data)
+        }
+        r.pos -= 3;
+
+        let offset = r.be(3)?;
+        let size = r.be(2)?;
+        let (run, fill) = if size == 0 {
+            (r.be(2)?, Some(r.byte()?))
+        } else { (size, None) };
+
+        if data.len() < offset + run {
+            data.resize(offset + run, 0);
+        }
+        match fill {
+            Some(v) => data[offset .. offset+run].iter_mut().for_each(|b| *b = v),
+            None => data[offset .. offset+run].copy_from_slice(r.bytes(run)?)
+        }
+    }
+}
+
+fn ups(data: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    if crc32(&data) != source_crc {
+        return Err("UPS source checksum mismatch")
+    }
+
+    let mut r = Reader { data: &patch[.. patch.len()-12], pos: 4 };
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    if source_size != data.len() {
+        return Err("UPS source size mismatch")
+    }
+
+    let mut out = data;
+    out.resize(target_size, 0);
+    let mut pos = 0;
+
+    while r.pos < r.data.len() {
+        pos += r.varint()?;
+        loop {
+            let x = r.byte()?;
+            if pos < out.len() {
+                out[pos] ^= x;
+            }
+            pos += 1;
+            if x == 0 { break }
+        }
+    }
+
+    if crc32(&out) != target_crc {
+        return Err("UPS target checksum mismatch")
+    }
+    Ok(out)
+}
+
+fn bps(source: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    if crc32(&source) != source_crc {
+        return Err("BPS source checksum mismatch")
+    }
+
+    let mut r = Reader { data: &patch[.. patch.len()-12], pos: 4 };
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+    if source_size != source.len() {
+        return Err("BPS source size mismatch")
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_rel: isize = 0;
+    let mut target_rel: isize = 0;
+    let signed = |v: usize| if v&1 != 0 { -((v >> 1) as isize) } else { (v >> 1) as isize };
+
+    // start .. start + length inside a buffer of len bytes
+    let range = |start: isize, length: usize, len: usize| -> Result<(usize, usize), &'static str> {
+        if start < 0 {
+            return Err("Invalid patch")
+        }
+        let start = start as usize;
+        let end = start.checked_add(length).filter(|end| *end <= len).ok_or("Invalid patch")?;
+        Ok((start, end))
+    };
+
+    while r.pos < r.data.len() {
+        let action = r.varint()?;
+        let length = (action >> 2) + 1;
+        if length > target_size - out.len() {  // never past the target size, so out.len() <= target_size
+            return Err("Invalid patch")
+        }
+
+        match action&0x3 {
+            0 => {  // source read
+                let (start, end) = range(out.len() as isize, length, source.len())?;
+                out.extend_from_slice(&source[start .. end]);
+            },
+            1 => {  // target read
+                out.extend_from_slice(r.bytes(length)?);
+            },
+            2 => {  // source copy
+                source_rel = source_rel.checked_add(signed(r.varint()?)).ok_or("Invalid patch")?;
+                let (start, end) = range(source_rel, length, source.len())?;
+                out.extend_from_slice(&source[start .. end]);
+                source_rel = end as isize;
+            },
+            _ => {  // target copy, can overlap with bytes written in this loop
+                target_rel = target_rel.checked_add(signed(r.varint()?)).ok_or("Invalid patch")?;
+                let (start, _) = range(target_rel, 1, out.len())?;  // has to start in what's written so far
+                for i in start .. start+length {
+                    let b = out[i];
+                    out.push(b);
+                }
+                target_rel = (start + length) as isize;
+            }
+        }
+    }
+
+    if out.len() != target_size || crc32(&out) != target_crc {
+        return Err("BPS target checksum mismatch")
+    }
+    Ok(out)
+}
+
+pub fn apply(data: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if patch.starts_with(b"PATCH") {
+        ips(data, patch)
+    } else if patch.starts_with(b"UPS1") {
+        ups(data, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps(data, patch)
+    } else {
+        Err("Unknown patch format")
+    }
+}
@@ -3,9 +3,17 @@
 use raylib::prelude::*;
 
 use crate::emulator::MODE;
-
-const GRAYSCALE_COLOR: [Color; 4] = [Color::WHITE, Color::LIGHTGRAY, Color::GRAY, Color::BLACK];
-const WH_RATIO: f32 = 160./144.;
+use crate::emulator::state::State;
+use crate::emulator::sgb::{self, Sgb};
+use crate::emulator::palettes::{self, CompatPalette, DmgPalette};
+use crate::emulator::color::{self, Correction};
+use crate::emulator::blend::Blend;
+use crate::emulator::scaler::Scaler;
+use crate::emulator::debug::Panel;
+
+// A CPU write lands on the last dot of its M-cycle.
+// Unverified: not yet compared with the mealybug m3_bgp_change/m3_obp0_change CGB reference images
+const LATCH_DOTS: u8 = 4;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum PPU_MODE {
@@ -15,7 +23,7 @@ pub enum PPU_MODE {
     DRAW
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Pixel_palette {  // can be used to differentiate between bg/window and sprite too
     BG,  // bg and window actually
     OBP0,
@@ -65,18 +73,42 @@ impl TileAttributes {
     }
 }
 
+// debug masks applied as pixels leave the FIFOs, LCDC and timings stay what the game set
+#[derive(Clone, Copy)]
+pub struct Layers {
+    pub bg: bool,
+    pub window: bool,
+    pub sprites: bool,
+    pub bg_palettes: u8,  // bit n hides CGB BG palette n
+    pub obj_palettes: u8,  // bit n hides CGB OBJ palette n
+    pub oam: u64,  // bit n hides OAM entry n
+}
+
+impl Layers {
+    pub fn new() -> Layers {
+        Layers {
+            bg: true,
+            window: true,
+            sprites: true,
+            bg_palettes: 0,
+            obj_palettes: 0,
+            oam: 0,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
-struct Sprite {
-    x: u8,
-    y: u8,
-    tile_location: u8,
-    x_flip: bool,
-    y_flip: bool,
-    priority: bool,
-    palette: Pixel_palette,
-    vram_bank: u8,
-    cgb_palette: Pixel_palette,
-    oam_addr: u8
+pub struct Sprite {
+    pub x: u8,
+    pub y: u8,
+    pub tile_location: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    pub priority: bool,
+    pub palette: Pixel_palette,
+    pub vram_bank: u8,
+    pub cgb_palette: Pixel_palette,
+    pub oam_addr: u8
 }
 
 impl Sprite {
@@ -99,6 +131,12 @@ impl Sprite {
         }
     }
 
+    pub fn attributes(&self) -> u8 {
+        let obp1 = match self.palette { Pixel_palette::OBP1 => 0x10, _ => 0 };
+        (self.priority as u8) << 7 | (self.y_flip as u8) << 6 | (self.x_flip as u8) << 5
+            | obp1 | self.vram_bank << 3 | usize::from(self.cgb_palette) as u8
+    }
+
     pub fn is_in_scanline(x: u8, y: u8, ly: u8, size: bool) -> bool {
         if x == 0 {
             return false;
@@ -112,7 +150,7 @@ impl Sprite {
 }
 
 
-fn compose_two_bytes(low: u8, high: u8) -> [u8; 8] {
+pub fn compose_two_bytes(low: u8, high: u8) -> [u8; 8] {
     let mut pixels: [u8; 8] = [0; 8];
 
     for (i, val) in [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01].iter().enumerate() {
@@ -152,16 +190,25 @@ pub struct Draw {
     pub handle: RaylibHandle,
     pub thread: RaylibThread,
     txt: Texture2D,
+    txt_size: (usize, usize),  // recreated when the scaled output size changes
     frame: [u8; 144*160*3],
+    sgb: bool,  // sgb_frame is shown instead of frame
+    pub sgb_frame: Vec<u8>,
+    color_lut: Vec<[u8; 3]>,  // CGB color -> rgb, see color::build_lut
+    pub blend: Blend,
+    output: Vec<u8>,  // frame (or sgb_frame) after blending
+    pub scaler: Scaler,
+    scaled: Vec<u8>,  // output after scaling, unused with Scaler::None
+    pub capture: bool,  // a recording needs the scaled picture even on skipped frames
+    pub panel: Option<Panel>,  // debug view shown instead of the game
+    panel_txt: Texture2D,
+    panel_size: (usize, usize),
+    pub mouse: Option<(usize, usize)>,  // panel pixel under the mouse
 
-    // tiles: Texture2D,
-    // tile_arr: Box<[u8; 192*128*3]>,
-
-    // tiles_dest_rect: Rectangle,
     frame_dest_rect: Rectangle,
-    // tiles_src_rect: Rectangle,
     frame_src_rect: Rectangle,
-    position: Vector2
+    position: Vector2,
+    pub skip_render: bool  // frame skipping, window isn't redrawn and input isn't polled
 }
 
 impl Draw {
@@ -172,81 +219,179 @@ impl Draw {
             .title("Gameboy emulator")
             .resizable()
             .build();
-        handle.set_target_fps(60);
 
-        let mut img = Image::gen_image_color(160, 144, Color::BLACK);
-        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
-        let txt = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
+        let txt = Draw::load_texture(&mut handle, &thread, 160, 144);
+        let panel_txt = Draw::load_texture(&mut handle, &thread, 1, 1);
 
-        
-        // let mut img = Image::gen_image_color(128, 192, Color::BLACK);
-        // img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
-        // let tiles = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
-        
         Draw {
             handle: handle,
             thread: thread,
             txt: txt,
+            txt_size: (160, 144),
             frame: [0; 144*160*3],
-            // tiles: tiles,
-            // tile_arr: Box::new([0; 192*128*3]),
+            sgb: false,
+            sgb_frame: vec![],
+            color_lut: color::build_lut(Correction::Raw),
+            blend: Blend::new(),
+            output: vec![],
+            scaler: Scaler::None,
+            scaled: vec![],
+            capture: false,
+            panel: None,
+            panel_txt,
+            panel_size: (1, 1),
+            mouse: None,
 
-            // tiles_dest_rect: Rectangle::new(0., 144.*2.+1., 256., 192.*2.),
             frame_dest_rect: Rectangle::new(0., 0., 160.*2., 144.*2.),
-            // tiles_src_rect: Rectangle::new(0., 0., 128., 192.),
             frame_src_rect: Rectangle::new(0., 0., 160., 144.),
-            position: Vector2::new(0., 0.)
+            position: Vector2::new(0., 0.),
+            skip_render: false
         }
     }
 
+    fn load_texture(handle: &mut RaylibHandle, thread: &RaylibThread, w: usize, h: usize) -> Texture2D {
+        let mut img = Image::gen_image_color(w as i32, h as i32, Color::BLACK);
+        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
+        handle.load_texture_from_image(thread, &img).expect("Couldnt load texture from image")
+    }
+
+    // unscaled size of the picture
+    fn size(&self) -> (usize, usize) {
+        if self.sgb { (sgb::WIDTH, sgb::HEIGHT) } else { (160, 144) }
+    }
+
     #[inline]
-    pub fn new_frame(&mut self, vram: &[u8]) {
-        // self.draw_vram_tiles(vram);
-        // self.tiles.update_texture(self.tile_arr.as_ref());
-        if self.handle.is_window_resized() {
-            let h = self.handle.get_screen_height() as f32;
-            let w = WH_RATIO * h;
-            let x = (w - self.handle.get_screen_width() as f32)/2.;
+    pub fn new_frame(&mut self) {
+        let frame = if self.sgb { &self.sgb_frame[..] } else { &self.frame[..] };
+        self.blend.apply(frame, &mut self.output);  // every frame, so skipped ones still fade
+
+        if self.skip_render && !self.capture {
+            return;
+        }
+
+        let (w, h) = self.size();
+        let f = self.scaler.factor();
+        if self.scaler != Scaler::None {
+            self.scaler.apply(&self.output, w, h, &mut self.scaled);
+        }
+        if self.skip_render {
+            return;
+        }
+        if self.txt_size != (w*f, h*f) {
+            self.txt = Draw::load_texture(&mut self.handle, &self.thread, w*f, h*f);
+            self.txt_size = (w*f, h*f);
+            self.frame_src_rect = Rectangle::new(0., 0., (w*f) as f32, (h*f) as f32);
+        }
 
-            self.frame_dest_rect = Rectangle::new(0., 0., w, h);
-            self.position = Vector2::new(x, 0.);
+        if self.handle.is_window_resized() {
+            self.fit_window();
+        }
+        if let Some(panel) = self.panel.take() {
+            self.draw_panel(&panel);
+            self.panel = Some(panel);
+            return;
         }
 
-        self.txt.update_texture(&self.frame);
+        self.txt.update_texture(if self.scaler == Scaler::None { &self.output } else { &self.scaled });
         let mut d = self.handle.begin_drawing(&self.thread);
         d.clear_background(Color::BLACK);
         d.draw_texture_pro(&self.txt, self.frame_src_rect, self.frame_dest_rect, self.position, 0., Color::WHITE);
-        // d.draw_texture_pro(&self.tiles, self.tiles_src_rect, self.tiles_dest_rect, Vector2::new(0., 0.), 0., Color::WHITE);
         d.draw_fps(0, 0);
     }
 
-    // #[inline]
-    // fn draw_vram_tiles(&mut self, vram: &[u8]) {
-    //     let mut vram_pos: usize;
-    //     let mut pixel_pos: usize;
-    //     'a: for i in 0 ..= 192 {
-    //         for j in 0 ..= 15 {
-    //             vram_pos = (i / 8) * 256 + j*16 + (i%8)*2;
-    //             if vram_pos > 0x17FF { break 'a; }
-    //             pixel_pos = (j*8 + i*128)*3;
-    //             let p = compose_two_bytes(vram[vram_pos], vram[vram_pos+1]);
-    //             for pix in p.iter() {
-    //                 let a = GRAYSCALE_COLOR[*pix as usize];
-    //                 self.tile_arr[pixel_pos] = a.r;
-    //                 self.tile_arr[pixel_pos+1] = a.g;
-    //                 self.tile_arr[pixel_pos+2] = a.b;
-    //                 pixel_pos += 3;
-    //             }
-    //         }
-    //     }
-    // }
+    // fitted to the window with the text in the top left corner, a panel without a picture
+    // is only text and its font grows with the window
+    fn draw_panel(&mut self, panel: &Panel) {
+        let (sw, sh) = (self.handle.get_screen_width() as f32, self.handle.get_screen_height() as f32);
+        let (w, h) = (panel.width as f32, panel.height as f32);
+        let zoom = (sw / w).min(sh / h);
+        let dest = Rectangle::new((sw - w*zoom)/2., (sh - h*zoom)/2., w*zoom, h*zoom);
+
+        if panel.width > 0 {
+            if self.panel_size != (panel.width, panel.height) {
+                self.panel_txt = Draw::load_texture(&mut self.handle, &self.thread, panel.width, panel.height);
+                self.panel_size = (panel.width, panel.height);
+            }
+            self.panel_txt.update_texture(&panel.rgb);
+
+            let m = self.handle.get_mouse_position();
+            let (x, y) = ((m.x - dest.x) / zoom, (m.y - dest.y) / zoom);
+            self.mouse = if x >= 0. && y >= 0. && x < w && y < h { Some((x as usize, y as usize)) } else { None };
+        } else {
+            self.mouse = None;
+        }
+
+        let columns = panel.text.iter().map(|l| l.chars().count()).max().unwrap_or(0) as f32;
+        let size = if panel.width > 0 { 10 } else {
+            ((sw - 8.) / (columns * 0.6)).min((sh - 8.) / (panel.text.len() as f32 * 1.2)).clamp(10., 40.) as i32
+        };
+        let (advance, line_height) = (size*6/10, size*12/10);
+
+        let mut d = self.handle.begin_drawing(&self.thread);
+        d.clear_background(Color::BLACK);
+        if panel.width > 0 {
+            d.draw_texture_pro(&self.panel_txt, Rectangle::new(0., 0., w, h), dest, Vector2::new(0., 0.), 0., Color::WHITE);
+        }
+        if !panel.text.is_empty() {
+            d.draw_rectangle(0, 0, columns as i32*advance + 8, panel.text.len() as i32*line_height + 4, Color::new(0, 0, 0, 192));
+            for (i, line) in panel.text.iter().enumerate() {
+                let color = if line.starts_with('*') { Color::ORANGE } else { Color::YELLOW };
+                for (j, c) in line.chars().enumerate() {  // one character at a time so columns line up
+                    d.draw_text(c.encode_utf8(&mut [0; 4]), 4 + j as i32*advance, 4 + i as i32*line_height, size, color);
+                }
+            }
+        }
+    }
+
+    // last picture as shown in the window, rgb with its size
+    pub fn output(&self) -> (&[u8], usize, usize) {
+        let (w, h) = self.size();
+        let f = self.scaler.factor();
+        (if self.scaler == Scaler::None { &self.output } else { &self.scaled }, w*f, h*f)
+    }
+
+    fn fit_window(&mut self) {
+        let h = self.handle.get_screen_height() as f32;
+        let w = self.frame_src_rect.width / self.frame_src_rect.height * h;
+        let x = (w - self.handle.get_screen_width() as f32)/2.;
+
+        self.frame_dest_rect = Rectangle::new(0., 0., w, h);
+        self.position = Vector2::new(x, 0.);
+    }
+
+    // switches the window to the 256x224 SGB picture
+    pub fn enable_sgb(&mut self) {
+        self.sgb = true;
+        self.sgb_frame = vec![0; sgb::WIDTH*sgb::HEIGHT*3];
+
+        self.frame_src_rect = Rectangle::new(0., 0., sgb::WIDTH as f32, sgb::HEIGHT as f32);
+        self.handle.set_window_size(sgb::WIDTH as i32*2, sgb::HEIGHT as i32*2);
+        self.fit_window();
+    }
+
+    // 1 is pressed: right, left, up, down, A, B, select, start from LSb
+    pub fn poll_joypad(&self) -> u8 {
+        use raylib::consts::KeyboardKey::{KEY_W, KEY_S, KEY_A, KEY_D, KEY_J, KEY_K, KEY_N, KEY_M};
+
+        [KEY_D, KEY_A, KEY_W, KEY_S, KEY_J, KEY_K, KEY_N, KEY_M].iter().enumerate()
+            .fold(0, |acc, (i, key)| acc | (self.handle.is_key_down(*key) as u8) << i)
+    }
+
+    pub fn set_color_correction(&mut self, mode: Correction) {
+        self.color_lut = color::build_lut(mode);
+    }
+
+    // CGB color (5 bits per channel) through the color correction table
+    #[inline]
+    pub fn rgb_correct(&self, color: Color) -> [u8; 3] {
+        self.color_lut[color.r as usize | (color.g as usize) << 5 | (color.b as usize) << 10]
+    }
 
     #[inline]
     pub fn draw_pixel_rgb_correct(&mut self, x: u8, y: u8, color: Color) {
         let pos = (y as usize * 160 + x as usize)*3;
-        self.frame[pos] = color.r << 3;
-        self.frame[pos+1] = color.g << 3;
-        self.frame[pos+2] = color.b << 3;
+        let rgb = self.rgb_correct(color);
+        self.frame[pos .. pos+3].copy_from_slice(&rgb);
     }
 
     #[inline]
@@ -280,8 +425,11 @@ pub struct Fetcher {
     tile_mode: FetcherTileMode,
     current_pixel_push: u8,
     discard_pixels: u8,
+    window_discard: u8,  // window pixels left of the screen when WX < 7
     current_sprite: Option<Sprite>,
     sprite_cycles: u8,
+    sprite_penalty: u8,  // dots the current sprite fetch stalls the FIFO for
+    penalty_tile: Option<(bool, i16)>,  // (window, tile) the last sprite waited for the BG fetch on
     data: [u8; 3],
     tile_attrib: TileAttributes
 }
@@ -296,9 +444,12 @@ impl Fetcher {
             tile_mode: FetcherTileMode::BG,
             current_pixel_push: 0,
             discard_pixels: 0,
+            window_discard: 0,
             data: [0; 3],
             current_sprite: None,
             sprite_cycles: 0,
+            sprite_penalty: 6,
+            penalty_tile: None,
             tile_attrib: TileAttributes::new(0)
         }
     }
@@ -309,7 +460,9 @@ pub struct PPU {
     cycles: u16,
     pub d: Draw,
     pub gb_mode: MODE,
-    color_map: [Color; 4],
+    color_map: [[Color; 4]; 3],  // DMG shades per Pixel_palette (BG, OBP0, OBP1)
+    pub sgb: Option<Sgb>,
+    pub compat: bool,  // DMG cartridge on CGB, shades go through bg_palette[0] and obj_palette[0..1]
 
     // lcdc bools
     lcd_enabled: bool,
@@ -322,16 +475,16 @@ pub struct PPU {
     bg_enabled: bool,
 
     // registers
-    lcdc: u8,  // FF40
+    pub lcdc: u8,  // FF40
     stat: u8,  // FF41
-    scy: u8,   // FF42
-    scx: u8,   // FF43
+    pub scy: u8,   // FF42
+    pub scx: u8,   // FF43
     ly: u8,    // FF44
     lyc: u8,   // FF45
     dma: u8,   // FF46
     palette: [u8; 3], // Order as in Pixel_palette enum
-    wy: u8,    // FF4A
-    wx: u8,    // FF4B
+    pub wy: u8,    // FF4A
+    pub wx: u8,    // FF4B
 
     // CGB background palette
     bg_index: u8,
@@ -347,29 +500,36 @@ pub struct PPU {
 
     // oam buffer sprites
     sprites: Vec<Sprite>,
+    pub line_sprites: Vec<Vec<u8>>,  // OAM entries the scan picked for each LY, for the debug views
+    pub line_timing: Vec<u16>,  // mode 3 length in dots for each LY, for the debug views
+    pub layers: Layers,
     FIFO: Vec<Pixel_FIFO>,
     FIFO_sprite: Vec<Pixel_FIFO>,
     fetcher: Fetcher,
+    pending: Vec<(u8, u16, u8)>,  // (dots left, addr, val) of register writes made during mode 3
+    palette_glitch: Option<(u16, u8)>,  // DMG palette write finishing on the next dot
     draw_timing: u16,
+    off_cycles: u32,  // dots since the last frame while the LCD is off
+    pub blank_frame: bool,  // a frame went by with the LCD off, taken like VBLANK
     window_line: u8,
     window_y_trigger: bool,
 
     // input per frame - 0 is pressed
     pub in_button: u8,     // p15 5th bit
     pub in_direction: u8,  // p14 4th bit
+    pub joypad: u8,  // latched input for the next frame, see Draw::poll_joypad
 }
 
 impl PPU {
     pub fn new() -> PPU {
-        //let cm = [Color::from((0xf0,0xff,0xf0,0xff)), Color::from((0x70,0x80,0x70,0xff)), Color::from((0x20, 0x30, 0x20, 0xff)), Color::from((0,0,0,255))];
-        let cm = GRAYSCALE_COLOR;
-        
-        PPU {
+        let mut ppu = PPU {
             mode: PPU_MODE::OAM,
             cycles: 0,
             d: Draw::new(),
             gb_mode: MODE::DMG,
-            color_map: cm,
+            color_map: [[Color::WHITE; 4]; 3],
+            sgb: None,
+            compat: false,
 
             // lcdc bools
             lcd_enabled: true,
@@ -404,30 +564,36 @@ impl PPU {
             obj_priority_mode: false,
 
             sprites: vec![],
+            line_sprites: vec![vec![]; 144],
+            line_timing: vec![0; 144],
+            layers: Layers::new(),
             FIFO: vec![],
             FIFO_sprite: vec![],
             fetcher: Fetcher::new(),
+            pending: vec![],
+            palette_glitch: None,
             draw_timing: 0,
+            off_cycles: 0,
+            blank_frame: false,
             window_line: 0,
             window_y_trigger: false,
 
             in_button: 0xF,
-            in_direction: 0xF
-        }
+            in_direction: 0xF,
+            joypad: 0
+        };
+        ppu.set_dmg_palette(&palettes::DEFAULT_DMG);
+        ppu
     }
 
     #[inline]
     fn update_input(&mut self, IF: &mut u8, input_select: &u8) {
-        use raylib::consts::KeyboardKey::{KEY_W, KEY_S, KEY_A, KEY_D, KEY_J, KEY_K, KEY_N, KEY_M};
-
-        let hl = &self.d.handle;
         let before_dir = self.in_direction;
         let before_butt = self.in_button;
 
-        self.in_direction = hl.is_key_up(KEY_D) as u8 | ((hl.is_key_up(KEY_A) as u8) << 1) | ((hl.is_key_up(KEY_W) as u8) << 2) | ((hl.is_key_up(KEY_S) as u8) << 3);
-        self.in_button = hl.is_key_up(KEY_J) as u8 | ((hl.is_key_up(KEY_K) as u8) << 1) | ((hl.is_key_up(KEY_N) as u8) << 2) | ((hl.is_key_up(KEY_M) as u8) << 3);
-    
-        
+        self.in_direction = !self.joypad & 0xF;
+        self.in_button = !(self.joypad >> 4) & 0xF;
+
         match input_select&0x30 {
             0x10 => {
                 if before_butt & (!self.in_button) != 0 {
@@ -467,7 +633,11 @@ impl PPU {
                     self.window_line = 0;
                     self.set_stat(PPU_MODE::OAM);
                 } else if old_en && !self.lcd_enabled {
+                    self.off_cycles = 0;
                     self.d.frame = [0; 144*160*3];
+                    if let Some(sgb) = &mut self.sgb {
+                        sgb.screen = [0; 160*144];
+                    }
                 }
                 
                 self.window_tilemap = val&0x40 != 0;
@@ -525,8 +695,59 @@ impl PPU {
         }
     }
 
+    // CPU write. Registers that change what mode 3 draws take effect on the dot the write cycle ends,
+    // so the pixel they start at lines up with the FIFO
+    pub fn write_latched(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 | 0xFF42 | 0xFF43 | 0xFF47 ..= 0xFF4B if self.lcd_enabled && self.mode == PPU_MODE::DRAW => {
+                self.pending.push((LATCH_DOTS, addr, val));
+            },
+            _ => self.write(addr, val)
+        }
+    }
+
+    fn latch_writes(&mut self) {
+        if let Some((addr, val)) = self.palette_glitch.take() {
+            self.write(addr, val);
+        }
+        if self.pending.is_empty() {
+            return;
+        }
+
+        for w in self.pending.iter_mut() {
+            w.0 -= 1;
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(|w| w.0 == 0);
+        self.pending = waiting;
+
+        for (_, addr, val) in due {
+            if self.gb_mode != MODE::CGB && !self.compat && (0xFF47 ..= 0xFF49).contains(&addr) {
+                // DMG palettes read as old | new for the dot the write lands on, a CGB running
+                // a DMG cartridge goes through its own palette RAM and doesn't do this
+                let old = self.read(addr);
+                self.write(addr, old | val);
+                self.palette_glitch = Some((addr, val));
+            } else {
+                self.write(addr, val);
+            }
+        }
+    }
+
+    // register write without the LCD on/off reset or the palette index increment
+    pub fn poke(&mut self, addr: u16, val: u8) -> Result<(), &'static str> {
+        match addr {
+            0xFF40 if (val ^ self.lcdc)&0x80 != 0 => return Err("the LCD can only be turned on or off by a write"),
+            0xFF44 => return Err("LY is read only"),
+            0xFF69 => cgb_set_color_byte_by_index(self.bg_index, &mut self.bg_palette, val),
+            0xFF6B => cgb_set_color_byte_by_index(self.obj_index, &mut self.obj_palette, val),
+            0xFF40 ..= 0xFF4B | 0xFF68 ..= 0xFF6C => self.write(addr, val),
+            _ => return Err("not a PPU register")
+        }
+        Ok(())
+    }
+
     #[inline]
-    pub fn read(&mut self, addr: u16) -> u8 {
+    pub fn read(&self, addr: u16) -> u8 {
         match addr {
             0xFF40 => self.lcdc,
             0xFF41 => self.stat,
@@ -550,6 +771,197 @@ impl PPU {
         }
     }
 
+    pub fn set_dmg_palette(&mut self, p: &DmgPalette) {
+        for (map, shades) in self.color_map.iter_mut().zip(p.iter()) {
+            for (c, rgb) in map.iter_mut().zip(shades.iter()) {
+                *c = Color::new((rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8, 255);
+            }
+        }
+    }
+
+    // rgb of the four color numbers as they are drawn with palette p, for the debug views
+    pub fn palette_rgb(&self, p: Pixel_palette) -> [[u8; 3]; 4] {
+        match p {
+            Pixel_palette::CGB_BG(i) => self.bg_palette[i as usize].map(|c| self.d.rgb_correct(c)),
+            Pixel_palette::CGB_OBJ(i) => self.obj_palette[i as usize].map(|c| self.d.rgb_correct(c)),
+            _ => {
+                let source = usize::from(p);
+                [0, 1, 2, 3].map(|i| {
+                    let shade = map_to_palette(i, self.palette[source]);
+                    if !self.compat {
+                        let c = self.color_map[source][shade];
+                        [c.r, c.g, c.b]
+                    } else if source == 0 {
+                        self.d.rgb_correct(self.bg_palette[0][shade])
+                    } else {
+                        self.d.rgb_correct(self.obj_palette[source-1][shade])
+                    }
+                })
+            }
+        }
+    }
+
+    // raw 15-bit color i of a CGB_BG or CGB_OBJ palette
+    pub fn cgb_color(&self, p: Pixel_palette, i: usize) -> Option<u16> {
+        let c = match p {
+            Pixel_palette::CGB_BG(n) => self.bg_palette[n as usize][i],
+            Pixel_palette::CGB_OBJ(n) => self.obj_palette[n as usize][i],
+            _ => return None
+        };
+        Some(c.r as u16 | (c.g as u16) << 5 | (c.b as u16) << 10)
+    }
+
+    // palette RAM write without going through BCPS/BCPD
+    pub fn set_cgb_color(&mut self, p: Pixel_palette, i: usize, val: u16) -> Result<(), &'static str> {
+        let c = match p {
+            Pixel_palette::CGB_BG(n) => &mut self.bg_palette[n as usize][i],
+            Pixel_palette::CGB_OBJ(n) => &mut self.obj_palette[n as usize][i],
+            _ => return Err("not a CGB palette")
+        };
+        *c = Color::new((val&0x1F) as u8, ((val >> 5)&0x1F) as u8, ((val >> 10)&0x1F) as u8, 255);
+        Ok(())
+    }
+
+    pub fn set_compat_palette(&mut self, p: &CompatPalette) {
+        let to_color = |c: u16| Color::new((c&0x1F) as u8, ((c >> 5)&0x1F) as u8, ((c >> 10)&0x1F) as u8, 255);
+
+        self.bg_palette[0] = p[0].map(to_color);
+        self.obj_palette[0] = p[1].map(to_color);
+        self.obj_palette[1] = p[2].map(to_color);
+        self.compat = true;
+    }
+
+    // taken at VBLANK, when the fetcher and FIFOs are empty
+    pub fn save_state(&self, s: &mut State) {
+        s.write_u8(self.mode as u8);
+        s.write_u16(self.cycles);
+        s.write_u8(self.gb_mode as u8);
+        s.write_bool(self.lcd_enabled);
+        s.write_bool(self.window_enabled);
+
+        s.write_u8(self.lcdc);
+        s.write_u8(self.stat);
+        s.write_u8(self.scy);
+        s.write_u8(self.scx);
+        s.write_u8(self.ly);
+        s.write_u8(self.lyc);
+        s.write_u8(self.dma);
+        s.write_bytes(&self.palette);
+        s.write_u8(self.wy);
+        s.write_u8(self.wx);
+
+        s.write_u8(self.bg_index);
+        s.write_u8(self.bg_ai);
+        s.write_u8(self.obj_index);
+        s.write_u8(self.obj_ai);
+        for c in self.bg_palette.iter().chain(self.obj_palette.iter()).flatten() {
+            s.write_u32(u32::from_le_bytes([c.r, c.g, c.b, c.a]));
+        }
+        s.write_bool(self.obj_priority_mode);
+
+        s.write_u8(self.sprites.len() as u8);
+        for i in 0 .. 10 {  // fixed size so snapshots can be diffed
+            let sprite = self.sprites.get(i).copied().unwrap_or_else(|| Sprite::new(&[0; 4], 0));
+            s.write_u8(sprite.y);
+            s.write_u8(sprite.x);
+            s.write_u8(sprite.tile_location);
+            s.write_u8(sprite.attributes());
+            s.write_u8(sprite.oam_addr);
+        }
+        s.write_u16(self.draw_timing);
+        s.write_u8(self.window_line);
+        s.write_bool(self.window_y_trigger);
+        s.write_u8(self.in_button);
+        s.write_u8(self.in_direction);
+        s.write_u8(self.joypad);
+        s.write_bool(self.compat);
+
+        match &self.sgb {
+            Some(sgb) => {
+                s.write_bool(true);
+                sgb.save_state(s);
+            },
+            None => s.write_bool(false)
+        }
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        self.mode = match s.read_u8() {
+            0 => PPU_MODE::HBLANK,
+            1 => PPU_MODE::VBLANK,
+            2 => PPU_MODE::OAM,
+            _ => PPU_MODE::DRAW
+        };
+        self.cycles = s.read_u16();
+        self.gb_mode = MODE::from(s.read_u8());
+
+        let lcd_enabled = s.read_bool();
+        let window_enabled = s.read_bool();
+        self.lcdc = s.read_u8();
+        self.lcd_enabled = true;  // skip the lcd on/off transition side effects
+        self.write(0xFF40, self.lcdc);
+        self.lcd_enabled = lcd_enabled;
+        self.window_enabled = window_enabled;
+
+        self.stat = s.read_u8();
+        self.scy = s.read_u8();
+        self.scx = s.read_u8();
+        self.ly = s.read_u8();
+        self.lyc = s.read_u8();
+        self.dma = s.read_u8();
+        s.read_bytes(&mut self.palette);
+        self.wy = s.read_u8();
+        self.wx = s.read_u8();
+
+        self.bg_index = s.read_u8();
+        self.bg_ai = s.read_u8();
+        self.obj_index = s.read_u8();
+        self.obj_ai = s.read_u8();
+        for c in self.bg_palette.iter_mut().chain(self.obj_palette.iter_mut()).flatten() {
+            let [r, g, b, a] = s.read_u32().to_le_bytes();
+            *c = Color::new(r, g, b, a);
+        }
+        self.obj_priority_mode = s.read_bool();
+
+        let count = s.read_u8() as usize;
+        self.sprites = vec![];
+        for i in 0 .. 10 {
+            let data = [s.read_u8(), s.read_u8(), s.read_u8(), s.read_u8()];
+            let oam_addr = s.read_u8();
+            if i < count {
+                self.sprites.push(Sprite::new(&data, oam_addr));
+            }
+        }
+        self.draw_timing = s.read_u16();
+        self.window_line = s.read_u8();
+        self.window_y_trigger = s.read_bool();
+        self.in_button = s.read_u8();
+        self.in_direction = s.read_u8();
+        self.joypad = s.read_u8();
+        self.compat = s.read_bool();
+
+        if s.read_bool() {
+            self.sgb.get_or_insert_with(Sgb::new).load_state(s);
+        }
+
+        self.fetcher = Fetcher::new();
+        self.FIFO = vec![];
+        self.FIFO_sprite = vec![];
+        self.pending = vec![];
+        self.palette_glitch = None;
+        self.off_cycles = 0;  // states are taken on frame boundaries
+        self.blank_frame = false;
+    }
+
+    fn end_frame(&mut self) {
+        if let Some(sgb) = &self.sgb {
+            if !self.d.skip_render || self.d.capture {
+                sgb.render(&mut self.d.sgb_frame);
+            }
+        }
+        self.d.new_frame();
+    }
+
     #[inline]
     fn set_stat(&mut self, mode: PPU_MODE) {
         self.stat = (self.stat&0xFC) | mode as u8;
@@ -561,13 +973,18 @@ impl PPU {
 
         if self.d.handle.window_should_close() { panic!("Window closed"); }
 
+        if !self.pending.is_empty() || self.palette_glitch.is_some() {
+            self.latch_writes();
+        }
+
         if !self.lcd_enabled {
-            if self.cycles % 65535 == 0 { // that doesnt need to be accurate
-                self.d.new_frame(vram);
+            self.off_cycles += 1;
+            if self.off_cycles == 70224 {  // frames go on at the usual rate with the screen off
+                self.off_cycles = 0;
+                self.end_frame();
                 self.update_input(IF, input_select);
-                self.cycles = 0;
+                self.blank_frame = true;
             }
-            self.cycles += 1;
             return;
         }
 
@@ -576,6 +993,11 @@ impl PPU {
                 if self.cycles == 79 {
                     self.mode = DRAW;
                     self.set_stat(DRAW);
+
+                    if let Some(line) = self.line_sprites.get_mut(self.ly as usize) {
+                        line.clear();
+                        line.extend(self.sprites.iter().map(|s| s.oam_addr / 4));
+                    }
                     
                     if self.wy == self.ly {
                         self.window_y_trigger = true;
@@ -602,7 +1024,9 @@ impl PPU {
                 if !a {
                     self.mode = HBLANK;
                     self.set_stat(HBLANK);
-                    // println!("{}", self.draw_timing);
+                    if let Some(t) = self.line_timing.get_mut(self.ly as usize) {
+                        *t = self.draw_timing;
+                    }
                     self.draw_timing = 0;
                     if self.stat&0x08 != 0 { *IF |= 0b10; }
                 }
@@ -628,6 +1052,9 @@ impl PPU {
                         self.mode = VBLANK;
                         self.set_stat(VBLANK);
                         *IF |= 0b1;
+                        if let Some(sgb) = &mut self.sgb {
+                            sgb.vblank(vram, self.lcdc);
+                        }
                         if self.stat&0x10 != 0 { *IF |= 0b10; }
                     } else {
                         self.mode = OAM;
@@ -659,7 +1086,7 @@ impl PPU {
                         self.mode = OAM;
                         self.ly = 0;
                         self.window_line = 0;
-                        self.d.new_frame(vram);
+                        self.end_frame();
                         self.update_input(IF, input_select);
                     }
                 } else {
@@ -669,6 +1096,44 @@ impl PPU {
         }
     }
 
+    // the FIFO is cleared when the window starts, so the fetcher mode tells which layer a pixel came from
+    fn hidden_bg(&self, pixel: &Pixel_FIFO) -> bool {
+        let layer = if self.fetcher.tile_mode == FetcherTileMode::WIN { self.layers.window } else { self.layers.bg };
+        let palette = pixel.bg_attrib.map_or(0, |a| a.palette);
+        !layer || (self.gb_mode == MODE::CGB && self.layers.bg_palettes&(1 << palette) != 0)
+    }
+
+    fn hidden_sprite(&self, pixel: &Pixel_FIFO) -> bool {
+        let palette = match pixel.palette {
+            Pixel_palette::CGB_OBJ(n) => self.layers.obj_palettes&(1 << n) != 0,
+            _ => false
+        };
+        !self.layers.sprites || palette || self.layers.oam&(1 << (pixel.oam_pos/4)) != 0
+    }
+
+    // Dots a sprite stalls mode 3 for: 6 for its own fetch, plus the wait for the BG fetch of the tile under
+    // its leftmost pixel, 5 dots minus how far into the tile that pixel is. Only the first sprite on a tile
+    // waits, one at OAM X 0 always takes 11.
+    // Unverified: not yet run against the mealybug m3_* or intr_2_mode0_timing_sprites ROMs
+    fn sprite_penalty(&mut self, sprite: &Sprite) -> u8 {
+        if sprite.x == 0 {
+            return 11
+        }
+
+        let window = self.fetcher.tile_mode == FetcherTileMode::WIN;
+        let pos = if window {
+            sprite.x as i16 + 7 - self.wx as i16
+        } else {
+            sprite.x as i16 + self.scx as i16
+        };
+        let tile = Some((window, pos.div_euclid(8)));
+        if self.fetcher.penalty_tile == tile {
+            return 6
+        }
+        self.fetcher.penalty_tile = tile;
+        6 + 5u8.saturating_sub(pos.rem_euclid(8) as u8)
+    }
+
     #[inline]
     pub fn fetcher_tick(&mut self, vram: &[u8]) -> bool {
         use FetcherMode::*;
@@ -676,10 +1141,10 @@ impl PPU {
 
         if let Some(mut sprite) = self.fetcher.current_sprite {
             self.fetcher.sprite_cycles += 1;
-            if self.fetcher.sprite_cycles >= 5 {
+            if self.fetcher.sprite_cycles >= self.fetcher.sprite_penalty - 1 {
                 self.fetcher.current_sprite = None;
                 self.fetcher.sprite_cycles = 0;
-            } else if self.fetcher.sprite_cycles == 4 {
+            } else if self.fetcher.sprite_cycles == self.fetcher.sprite_penalty - 2 {
                 let (low, high) = {
                     let mut data_pos = if self.fetcher.current_sprite.unwrap().y_flip {
                         if self.sprite_size {
@@ -752,14 +1217,6 @@ impl PPU {
             return true;
         }
 
-        if self.fetcher.tile_mode == BG && self.window_enabled && self.fetcher.current_pixel_push+7 == self.wx && self.window_y_trigger {
-            self.FIFO = vec![];
-            self.fetcher.tile_mode = WIN;
-            self.fetcher.cycles = 0;
-            self.fetcher.lx = 0;
-            self.fetcher.mode = TILE_DATA;
-        }
-
         if self.fetcher.discard_pixels > 1 {
             if self.FIFO.len() > 0 {
                 self.FIFO.remove(0);
@@ -861,46 +1318,82 @@ impl PPU {
             }
 
             if self.FIFO.len() > 0 {
-                if self.fetcher.discard_pixels == 0 && self.scx%8 != 0 {
-                    self.fetcher.discard_pixels = self.scx%8 + 1;
+                // the window starts once pixels are coming out, so the first BG fetch is always paid for.
+                // This dot is the first of the 6 the window fetch takes. WX < 7 starts it at the first pixel
+                // once the SCX discard is done, with the window's first 7 - WX pixels dropped one per dot
+                let discarded = self.scx.is_multiple_of(8) || self.fetcher.discard_pixels == 1;
+                let start = if self.wx < 7 { self.fetcher.current_pixel_push == 0 && discarded } else { self.fetcher.current_pixel_push+7 == self.wx };
+                if self.fetcher.tile_mode == BG && self.window_enabled && start && self.window_y_trigger {
+                    self.FIFO = vec![];
+                    self.fetcher.window_discard = 7u8.saturating_sub(self.wx);
+                    self.fetcher.tile_mode = WIN;
+                    self.fetcher.cycles = 1;
+                    self.fetcher.lx = 0;
+                    self.fetcher.mode = TILE_DATA;
                     return true;
                 }
 
-                if self.sprites.len() > 0 {
-                    for (i, sprite) in self.sprites.iter().enumerate() {
-                        if self.fetcher.current_pixel_push + 8 >= sprite.x {
-                            if self.sprite_enabled {
-                                self.fetcher.current_sprite = Some(*sprite);
-                                self.sprites.remove(i);
-                                return true;
-                            }
-                        }
+                if self.fetcher.window_discard > 0 && self.fetcher.tile_mode == WIN {
+                    self.fetcher.window_discard -= 1;
+                    self.FIFO.remove(0);
+                    return true;
+                }
+
+                if self.fetcher.discard_pixels == 0 && !self.scx.is_multiple_of(8) && self.fetcher.tile_mode == BG {
+                    self.fetcher.discard_pixels = self.scx%8;  // one dot per discarded pixel, starting with this one
+                    self.FIFO.remove(0);
+                    return true;
+                }
+
+                if self.sprite_enabled {
+                    if let Some(i) = self.sprites.iter().position(|s| self.fetcher.current_pixel_push + 8 >= s.x) {
+                        let sprite = self.sprites.remove(i);
+                        self.fetcher.sprite_penalty = self.sprite_penalty(&sprite);
+                        self.fetcher.current_sprite = Some(sprite);
+                        return true;
                     }
                 }
 
                 let pixel = self.FIFO.remove(0);
+                let hidden = self.hidden_bg(&pixel);
 
-                if self.gb_mode == MODE::DMG {
-                    let mut color = self.color_map[map_to_palette(pixel.color, self.palette[usize::from(Pixel_palette::BG)])];
-                    if !self.bg_enabled {
-                        color = Color::WHITE;
+                if self.gb_mode != MODE::CGB {
+                    let mut shade = map_to_palette(pixel.color, self.palette[usize::from(Pixel_palette::BG)]);
+                    let mut source = 0;  // Pixel_palette of the pixel that won, BG/OBP0/OBP1
+                    if !self.bg_enabled || hidden {
+                        shade = 0;
                     }
 
                     if self.FIFO_sprite.len() > 0 {
                         let sprite_pixel = self.FIFO_sprite.remove(0);
-                        if sprite_pixel.color != 0 && (!sprite_pixel.priority || color == Color::WHITE) {
-                            color = self.color_map[map_to_palette(sprite_pixel.color, self.palette[usize::from(sprite_pixel.palette)])];
+                        if sprite_pixel.color != 0 && !self.hidden_sprite(&sprite_pixel) && (!sprite_pixel.priority || shade == 0) {
+                            shade = map_to_palette(sprite_pixel.color, self.palette[usize::from(sprite_pixel.palette)]);
+                            source = usize::from(sprite_pixel.palette);
                         }
                     }
-                    self.d.draw_pixel(self.fetcher.current_pixel_push, self.ly, color);
+
+                    match &mut self.sgb {  // SGB colors the final shade by screen area
+                        Some(sgb) => sgb.screen[self.ly as usize*160 + self.fetcher.current_pixel_push as usize] = shade as u8,
+                        None if self.compat => {
+                            let color = if source == 0 { self.bg_palette[0][shade] } else { self.obj_palette[source-1][shade] };
+                            self.d.draw_pixel_rgb_correct(self.fetcher.current_pixel_push, self.ly, color);
+                        },
+                        None => self.d.draw_pixel(self.fetcher.current_pixel_push, self.ly, self.color_map[source][shade])
+                    }
                     self.fetcher.current_pixel_push += 1;
                 } else {
-                    let mut color = self.bg_palette[pixel.bg_attrib.unwrap().palette as usize][pixel.color as usize];
+                    let attrib = pixel.bg_attrib.unwrap();
+                    let (bg_color, bg_priority) = if hidden { (0, false) } else { (pixel.color, attrib.priority) };
+                    let mut color = if hidden {
+                        Color::new(0x1F, 0x1F, 0x1F, 0xFF)
+                    } else {
+                        self.bg_palette[attrib.palette as usize][pixel.color as usize]
+                    };
 
                     if self.FIFO_sprite.len() > 0 {
                         let sprite_pixel = self.FIFO_sprite.remove(0);
-                        if sprite_pixel.color != 0 {
-                            if ((!sprite_pixel.priority || pixel.color == 0) && !pixel.bg_attrib.unwrap().priority) || (pixel.bg_attrib.unwrap().priority && pixel.color == 0) || !self.bg_enabled {
+                        if sprite_pixel.color != 0 && !self.hidden_sprite(&sprite_pixel) {
+                            if bg_color == 0 || !self.bg_enabled || (!sprite_pixel.priority && !bg_priority) {
                                 color = self.obj_palette[usize::from(sprite_pixel.palette)][sprite_pixel.color as usize];
                             }
                         }
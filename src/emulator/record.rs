@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::gif::GifWriter;
+use crate::emulator::speed::FRAME_RATE;
+
+// Records the post-processed picture once per emulated frame. .gif keeps every other frame
+// (browsers clamp shorter delays), anything else is written as Y4M with a WAV of the APU mix next to it.
+
+pub const AUDIO_RATE: u32 = 4194304 / 87;  // one stereo sample every 87 cycles, see APU::capture
+
+struct WavWriter {
+    out: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    fn new(p: &Path) -> io::Result<WavWriter> {
+        let mut out = BufWriter::new(File::create(p)?);
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;  // PCM
+        out.write_all(&2u16.to_le_bytes())?;  // stereo
+        out.write_all(&AUDIO_RATE.to_le_bytes())?;
+        out.write_all(&(AUDIO_RATE*4).to_le_bytes())?;  // bytes per second
+        out.write_all(&4u16.to_le_bytes())?;  // bytes per frame
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data\0\0\0\0")?;
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        for s in samples {
+            self.out.write_all(&s.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+// 4:4:4 planes, BT.601 limited range
+fn write_y4m_frame(out: &mut impl Write, rgb: &[u8]) -> io::Result<()> {
+    let mut planes: [Vec<u8>; 3] = Default::default();
+
+    for p in rgb.chunks(3) {
+        let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+        planes[0].push(((66*r + 129*g + 25*b + 128) >> 8) as u8 + 16);
+        planes[1].push(((-38*r - 74*g + 112*b + 128) >> 8) as u8 ^ 0x80);
+        planes[2].push(((112*r - 94*g - 18*b + 128) >> 8) as u8 ^ 0x80);
+    }
+
+    out.write_all(b"FRAME\n")?;
+    for plane in planes.iter() {
+        out.write_all(plane)?;
+    }
+    Ok(())
+}
+
+enum Sink {
+    Gif(GifWriter<BufWriter<File>>),
+    Y4m { video: BufWriter<File>, audio: WavWriter },
+}
+
+pub struct Recorder {
+    sink: Sink,
+    path: PathBuf,
+    size: (usize, usize),
+    frames: u32,
+    gif_time: u32,  // centiseconds written so far
+}
+
+impl Recorder {
+    pub fn start(p: &Path, width: usize, height: usize) -> Result<Recorder, Box<dyn Error>> {
+        let is_gif = p.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+
+        let sink = if is_gif {
+            Sink::Gif(GifWriter::new(BufWriter::new(File::create(p)?), width as u16, height as u16)?)
+        } else {
+            let mut video = BufWriter::new(File::create(p)?);
+            writeln!(video, "YUV4MPEG2 W{} H{} F4194304:70224 Ip A1:1 C444", width, height)?;
+            Sink::Y4m { video, audio: WavWriter::new(&p.with_extension("wav"))? }
+        };
+
+        println!("Recording video to {}", p.display());
+        Ok(Recorder {
+            sink,
+            path: p.to_path_buf(),
+            size: (width, height),
+            frames: 0,
+            gif_time: 0,
+        })
+    }
+
+    // audio is the interleaved stereo mix since the last frame
+    pub fn frame(&mut self, rgb: &[u8], width: usize, height: usize, audio: &[i16]) -> Result<(), Box<dyn Error>> {
+        if (width, height) != self.size {
+            return Err("output size changed".into())
+        }
+
+        match &mut self.sink {
+            Sink::Gif(gif) => {
+                if self.frames.is_multiple_of(2) {
+                    let end = ((self.frames + 2) as f64 * 100. / FRAME_RATE) as u32;
+                    gif.frame(rgb, (end - self.gif_time) as u16)?;
+                    self.gif_time = end;
+                }
+            },
+            Sink::Y4m { video, audio: wav } => {
+                write_y4m_frame(video, rgb)?;
+                wav.write(audio)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.sink {
+            Sink::Gif(gif) => { gif.finish()?; },
+            Sink::Y4m { mut video, audio } => {
+                video.flush()?;
+                audio.finish()?;
+            }
+        }
+        println!("Recorded {} frames to {}", self.frames, self.path.display());
+        Ok(())
+    }
+}
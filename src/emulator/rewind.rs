@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+
+// Snapshots are taken every frame. Only the newest one is kept whole, older ones are stored as
+// the xor against their successor, run length encoded, so walking back is newest ^ delta ^ delta...
+
+fn write_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let b = data[*pos];
+        *pos += 1;
+        v |= ((b&0x7F) as usize) << shift;
+        if b&0x80 == 0 { return v }
+        shift += 7;
+    }
+}
+
+// (zero run, literal count, literals)*
+fn compress_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+
+    while i < old.len() {
+        let start = i;
+        while i < old.len() && old[i] == new[i] { i += 1; }
+        let zeros = i - start;
+
+        let start = i;
+        while i < old.len() && (old[i] != new[i] || (i+1 < old.len() && old[i+1] != new[i+1])) { i += 1; }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - start);
+        out.extend(old[start .. i].iter().zip(new[start .. i].iter()).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+fn apply_delta(data: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for b in delta[pos .. pos+literals].iter() {
+            data[i] ^= b;
+            i += 1;
+        }
+        pos += literals;
+    }
+}
+
+pub struct Rewind {
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,  // back is the delta from newest to the one before it
+    used: usize,
+    pub max_size: usize,  // bytes, keyframe included
+}
+
+impl Rewind {
+    pub fn new(max_size: usize) -> Rewind {
+        Rewind {
+            newest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+            max_size,
+        }
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(old) = self.newest.take() {
+            if old.len() == snapshot.len() {
+                let delta = compress_delta(&old, &snapshot);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            } else {  // machine layout changed, older history is unusable
+                self.deltas.clear();
+                self.used = 0;
+            }
+        }
+
+        while self.used + snapshot.len() > self.max_size {
+            match self.deltas.pop_front() {
+                Some(d) => self.used -= d.len(),
+                None => break
+            }
+        }
+        self.newest = Some(snapshot);
+    }
+
+    // steps one snapshot back, stays on the oldest one once history runs out
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let data = self.newest.as_mut()?;
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used -= delta.len();
+            apply_delta(data, &delta);
+        }
+        Some(data.clone())
+    }
+}
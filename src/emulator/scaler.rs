@@ -0,0 +1,186 @@
+// CPU side upscaling of the rgb output, so every consumer (window, screenshots, video) sees the same picture
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Scaler {
+    None,
+    Integer(u8),  // nearest neighbour
+    Scale2x,      // AdvMAME2x / EPX
+    Scale3x,      // AdvMAME3x
+    Xbr2x,        // xBR level 1
+    Grid(u8),     // DMG dot matrix, gaps show the lighter LCD substrate
+}
+
+impl Scaler {
+    // none, 2x-8x, scale2x, scale3x, xbr, grid or grid:<3-8>
+    pub fn parse(s: &str) -> Result<Scaler, &'static str> {
+        let factor = |f: &str| match f.parse::<u8>() {
+            Ok(f) if (2 ..= 8).contains(&f) => Ok(f),
+            _ => Err("scale factor has to be between 2 and 8")
+        };
+
+        match s {
+            "none" => Ok(Scaler::None),
+            "scale2x" => Ok(Scaler::Scale2x),
+            "scale3x" => Ok(Scaler::Scale3x),
+            "xbr" => Ok(Scaler::Xbr2x),
+            "grid" => Ok(Scaler::Grid(3)),
+            _ => {
+                if let Some(f) = s.strip_prefix("grid:") {
+                    Ok(Scaler::Grid(factor(f)?.max(3)))
+                } else if let Some(f) = s.strip_suffix('x') {
+                    Ok(Scaler::Integer(factor(f)?))
+                } else {
+                    Err("scaler is one of none, 2x-8x, scale2x, scale3x, xbr, grid[:n]")
+                }
+            }
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match self {
+            Scaler::None => 1,
+            Scaler::Integer(f) | Scaler::Grid(f) => *f as usize,
+            Scaler::Scale2x | Scaler::Xbr2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    // rgb src of w*h pixels into out, (w*factor)*(h*factor) pixels
+    pub fn apply(&self, src: &[u8], w: usize, h: usize, out: &mut Vec<u8>) {
+        let f = self.factor();
+        let img = Pixels::from_rgb(src, w, h);
+        let mut dst = vec![0u32; w*f * h*f];
+        let mut block = [0u32; 8*8];
+
+        for y in 0 .. h {
+            for x in 0 .. w {
+                let (x_, y_) = (x as isize, y as isize);
+                let block = &mut block[.. f*f];
+                match self {
+                    Scaler::None | Scaler::Integer(_) => block.fill(img.get(x_, y_)),
+                    Scaler::Scale2x => block.copy_from_slice(&scale2x(&img, x_, y_)),
+                    Scaler::Scale3x => block.copy_from_slice(&scale3x(&img, x_, y_)),
+                    Scaler::Xbr2x => block.copy_from_slice(&xbr2x(&img, x_, y_)),
+                    Scaler::Grid(_) => grid(img.get(x_, y_), block, f),
+                }
+
+                for (i, p) in block.iter().enumerate() {
+                    dst[(y*f + i/f) * w*f + x*f + i%f] = *p;
+                }
+            }
+        }
+
+        out.clear();
+        out.extend(dst.iter().flat_map(|p| [(p >> 16) as u8, (p >> 8) as u8, *p as u8]));
+    }
+}
+
+struct Pixels {
+    pixels: Vec<u32>,  // 0xRRGGBB
+    w: isize,
+    h: isize,
+}
+
+impl Pixels {
+    fn from_rgb(src: &[u8], w: usize, h: usize) -> Pixels {
+        Pixels {
+            pixels: src.chunks(3).map(|p| (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32).collect(),
+            w: w as isize,
+            h: h as isize,
+        }
+    }
+
+    // edges are clamped
+    fn get(&self, x: isize, y: isize) -> u32 {
+        let x = x.clamp(0, self.w-1);
+        let y = y.clamp(0, self.h-1);
+        self.pixels[(y*self.w + x) as usize]
+    }
+}
+
+fn scale2x(img: &Pixels, x: isize, y: isize) -> [u32; 4] {
+    let p = img.get(x, y);
+    let a = img.get(x, y-1);
+    let b = img.get(x+1, y);
+    let c = img.get(x-1, y);
+    let d = img.get(x, y+1);
+
+    [
+        if c == a && c != d && a != b { a } else { p },
+        if a == b && a != c && b != d { b } else { p },
+        if d == c && d != b && c != a { c } else { p },
+        if b == d && b != a && d != c { d } else { p },
+    ]
+}
+
+fn scale3x(img: &Pixels, x: isize, y: isize) -> [u32; 9] {
+    let [a, b, c] = [img.get(x-1, y-1), img.get(x, y-1), img.get(x+1, y-1)];
+    let [d, e, f] = [img.get(x-1, y), img.get(x, y), img.get(x+1, y)];
+    let [g, h, i] = [img.get(x-1, y+1), img.get(x, y+1), img.get(x+1, y+1)];
+
+    if b == h || d == f {
+        return [e; 9]
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+        e,
+        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+fn yuv(p: u32) -> [i32; 3] {
+    let (r, g, b) = ((p >> 16) as i32&0xFF, (p >> 8) as i32&0xFF, p as i32&0xFF);
+    [
+        (299*r + 587*g + 114*b) / 1000,
+        (-169*r - 331*g + 500*b) / 1000,
+        (500*r - 419*g - 81*b) / 1000,
+    ]
+}
+
+fn distance(a: u32, b: u32) -> i32 {
+    let (a, b) = (yuv(a), yuv(b));
+    48*(a[0]-b[0]).abs() + 7*(a[1]-b[1]).abs() + 6*(a[2]-b[2]).abs()
+}
+
+fn mix(a: u32, b: u32) -> u32 {
+    ((a&0xFEFEFE) >> 1) + ((b&0xFEFEFE) >> 1) + (a&b&0x010101)
+}
+
+// bottom right corner with (sx, sy) mirroring it to the other three
+fn xbr_corner(img: &Pixels, x: isize, y: isize, sx: isize, sy: isize) -> u32 {
+    let px = |dx: isize, dy: isize| img.get(x + dx*sx, y + dy*sy);
+    let e = px(0, 0);
+    let (b, c, d, f) = (px(0, -1), px(1, -1), px(-1, 0), px(1, 0));
+    let (g, h, i) = (px(-1, 1), px(0, 1), px(1, 1));
+    let (f4, h5, i4, i5) = (px(2, 0), px(0, 2), px(2, 1), px(1, 2));
+
+    let edge = distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4*distance(h, f);
+    let across = distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4*distance(e, i);
+
+    if edge < across {
+        let new = if distance(e, f) <= distance(e, h) { f } else { h };
+        mix(e, new)
+    } else { e }
+}
+
+fn xbr2x(img: &Pixels, x: isize, y: isize) -> [u32; 4] {
+    [
+        xbr_corner(img, x, y, -1, -1),
+        xbr_corner(img, x, y, 1, -1),
+        xbr_corner(img, x, y, -1, 1),
+        xbr_corner(img, x, y, 1, 1),
+    ]
+}
+
+fn grid(p: u32, block: &mut [u32], f: usize) {
+    let gap = mix(p, 0xFFFFFF);
+    for (i, b) in block.iter_mut().enumerate() {
+        *b = if i%f == f-1 || i/f == f-1 { gap } else { p };
+    }
+}
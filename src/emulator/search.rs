@@ -0,0 +1,185 @@
+use crate::emulator::{Memory, MODE};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Region {
+    Wram,     // 0xC000 - 0xDFFF, all CGB banks
+    Hram,     // 0xFF80 - 0xFFFE
+    CartRAM,  // 0xA000 - 0xBFFF, all cartridge banks
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Width {
+    Byte,
+    Word,   // little endian
+    BCD8,   // 2 digits
+    BCD16,  // 4 digits, little endian
+}
+
+impl Width {
+    fn bytes(&self) -> usize {
+        match self {
+            Width::Byte | Width::BCD8 => 1,
+            Width::Word | Width::BCD16 => 2,
+        }
+    }
+
+    fn from_bcd(v: u8) -> Option<u32> {
+        if v&0xF > 9 || v >> 4 > 9 {
+            return None
+        }
+        Some((v >> 4) as u32 * 10 + (v&0xF) as u32)
+    }
+
+    fn to_bcd(v: u32) -> u8 {
+        (((v / 10) % 10) << 4 | (v % 10)) as u8
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Option<u32> {
+        match self {
+            Width::Byte => Some(data[0] as u32),
+            Width::Word => Some(data[0] as u32 | (data[1] as u32) << 8),
+            Width::BCD8 => Width::from_bcd(data[0]),
+            Width::BCD16 => Some(Width::from_bcd(data[0])? + Width::from_bcd(data[1])? * 100),
+        }
+    }
+
+    pub fn encode(&self, val: u32) -> Vec<u8> {
+        match self {
+            Width::Byte => vec![val as u8],
+            Width::Word => vec![val as u8, (val >> 8) as u8],
+            Width::BCD8 => vec![Width::to_bcd(val)],
+            Width::BCD16 => vec![Width::to_bcd(val % 100), Width::to_bcd(val / 100)],
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Compare {
+    Equal,      // unchanged since last snapshot
+    Changed,
+    Increased,
+    Decreased,
+    Value(u32),
+}
+
+impl Compare {
+    fn matches(&self, old: u32, new: u32) -> bool {
+        match *self {
+            Compare::Equal => new == old,
+            Compare::Changed => new != old,
+            Compare::Increased => new > old,
+            Compare::Decreased => new < old,
+            Compare::Value(v) => new == v,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Candidate {
+    pub region: Region,
+    pub offset: usize,
+}
+
+impl Candidate {
+    pub fn address(&self) -> (u16, u8) {  // (bus address, bank)
+        match self.region {
+            Region::Wram => {
+                let bank = self.offset / 0x1000;
+                let base = if bank == 0 { 0xC000 } else { 0xD000 };
+                (base + (self.offset&0xFFF) as u16, bank as u8)
+            },
+            Region::Hram => (0xFF80 + self.offset as u16, 0),
+            Region::CartRAM => (0xA000 + (self.offset&0x1FFF) as u16, (self.offset / 0x2000) as u8),
+        }
+    }
+}
+
+struct Snapshot {
+    wram: Vec<u8>,
+    hram: Vec<u8>,
+    cart_ram: Vec<u8>,
+}
+
+impl Snapshot {
+    fn take(mem: &Memory) -> Snapshot {
+        let wram_size = if mem.mode == MODE::CGB { 0x8000 } else { 0x2000 };
+
+        Snapshot {
+            wram: mem.ram[..wram_size].to_vec(),
+            hram: mem.hram.to_vec(),
+            cart_ram: mem.cart.mbc().ram_data().to_vec(),
+        }
+    }
+
+    fn region(&self, r: Region) -> &[u8] {
+        match r {
+            Region::Wram => &self.wram,
+            Region::Hram => &self.hram,
+            Region::CartRAM => &self.cart_ram,
+        }
+    }
+
+    fn value(&self, c: &Candidate, width: Width) -> Option<u32> {
+        let data = self.region(c.region);
+        if c.offset + width.bytes() > data.len() {
+            return None
+        }
+        width.decode(&data[c.offset ..])
+    }
+}
+
+pub struct RamSearch {
+    pub width: Width,
+    pub candidates: Vec<Candidate>,
+    snapshot: Snapshot,
+}
+
+impl RamSearch {
+    pub fn new(mem: &Memory, width: Width) -> RamSearch {
+        let snapshot = Snapshot::take(mem);
+        let mut candidates = vec![];
+
+        for region in [Region::Wram, Region::Hram, Region::CartRAM].iter() {
+            for offset in 0 .. snapshot.region(*region).len() {
+                let c = Candidate { region: *region, offset };
+                if snapshot.value(&c, width).is_some() {
+                    candidates.push(c);
+                }
+            }
+        }
+
+        RamSearch { width, candidates, snapshot }
+    }
+
+    pub fn filter(&mut self, mem: &Memory, cmp: Compare) {
+        let snapshot = Snapshot::take(mem);
+        let width = self.width;
+        let old = &self.snapshot;
+
+        self.candidates.retain(|c| {
+            match (old.value(c, width), snapshot.value(c, width)) {
+                (Some(o), Some(n)) => cmp.matches(o, n),
+                _ => false
+            }
+        });
+        self.snapshot = snapshot;
+    }
+
+    pub fn value(&self, c: &Candidate) -> Option<u32> {
+        self.snapshot.value(c, self.width)
+    }
+
+    // GameShark codes writing `val` to the candidate, one per byte as a word can cross into another bank
+    pub fn to_game_shark(&self, c: &Candidate, val: u32) -> Vec<String> {
+        self.width.encode(val).iter().enumerate().map(|(i, byte)| {
+            let byte_candidate = Candidate { region: c.region, offset: c.offset + i };
+            let (addr, bank) = byte_candidate.address();
+            let code_type = match c.region {
+                Region::Wram if bank >= 1 => 0x90 | bank,
+                Region::CartRAM => 0x80 | bank,
+                _ => 0x01
+            };
+            format!("{:02X}{:02X}{:02X}{:02X}", code_type, byte, addr as u8, addr >> 8)
+        }).collect()
+    }
+}
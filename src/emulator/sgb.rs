@@ -0,0 +1,433 @@
+use crate::emulator::state::State;
+
+// Super Game Boy: command packets are sent bit by bit over P1, 16 bytes per packet, up to 7 packets
+// per command. The SNES side colors the 4 dmg shades per 8x8 screen cell and draws a 256x224 border.
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 224;
+const SCREEN_X: usize = 48;  // game screen position inside the border
+const SCREEN_Y: usize = 40;
+
+const GRAYSCALE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+// reads 4kB "displayed" by the bg map, which is how the SNES side grabs data from vram
+fn vram_transfer(vram: &[u8], lcdc: u8) -> Vec<u8> {
+    let map = if lcdc&0x08 != 0 { 0x1C00 } else { 0x1800 };
+    let mut data = Vec::with_capacity(0x1000);
+
+    for i in 0 .. 256 {
+        let tile = vram[map + (i/20)*32 + i%20];
+        let pos = if lcdc&0x10 != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as isize * 16) as usize
+        };
+        data.extend_from_slice(&vram[pos .. pos+16]);
+    }
+    data
+}
+
+fn rgb(color: u16) -> [u8; 3] {
+    [((color&0x1F) as u8) << 3, (((color >> 5)&0x1F) as u8) << 3, (((color >> 10)&0x1F) as u8) << 3]
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    data[pos] as u16 | (data[pos+1] as u16) << 8
+}
+
+pub struct Sgb {
+    pub screen: [u8; 160*144],  // dmg shades (after BGP/OBP) written by the ppu
+    palettes: [[u16; 4]; 4],  // color 0 is shared, taken from palette 0
+    attr_map: [u8; 20*18],  // palette per 8x8 screen cell
+    mask: u8,  // MASK_EN: 0 - off, 1 - freeze, 2 - black, 3 - color 0
+
+    system_palettes: Vec<u8>,  // PAL_TRN, 512 palettes of 4 colors
+    attr_files: Vec<u8>,  // ATTR_TRN, 45 files of 90 bytes
+    border_tiles: Vec<u8>,  // CHR_TRN, 256 4bpp tiles
+    border_map: Vec<u8>,  // PCT_TRN, 32x32 entries then palettes 4-7
+    transfer: Option<u8>,  // command waiting for vram data on the next VBLANK
+
+    // packet receiver
+    last_p1: u8,
+    receiving: bool,
+    bit: usize,
+    packet: [u8; 16],
+    data: Vec<u8>,
+    packets_left: u8,
+
+    // MLT_REQ
+    players: u8,
+    player: u8,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb {
+            screen: [0; 160*144],
+            palettes: [GRAYSCALE; 4],
+            attr_map: [0; 20*18],
+            mask: 0,
+
+            system_palettes: vec![0; 0x1000],
+            attr_files: vec![0; 0x1000],
+            border_tiles: vec![0; 0x2000],
+            border_map: vec![0; 0x1000],
+            transfer: None,
+
+            last_p1: 0x30,
+            receiving: false,
+            bit: 0,
+            packet: [0; 16],
+            data: vec![],
+            packets_left: 0,
+
+            players: 1,
+            player: 0,
+        }
+    }
+
+    // lower nibble of P1 when neither buttons nor directions are selected
+    pub fn joypad_id(&self) -> u8 {
+        0xF - self.player
+    }
+
+    pub fn is_player_one(&self) -> bool {
+        self.player == 0
+    }
+
+    // 0x00 - reset, 0x10 - "1", 0x20 - "0", each pulse followed by 0x30
+    pub fn write_p1(&mut self, val: u8) {
+        let sel = val&0x30;
+        if sel == self.last_p1 {
+            return;
+        }
+
+        match sel {
+            0x00 => {
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; 16];
+            },
+            0x10 | 0x20 if self.receiving && self.last_p1 == 0x30 => {
+                if self.bit == 128 {  // stop bit, has to be 0
+                    self.receiving = false;
+                    if sel == 0x20 {
+                        self.packet_done();
+                    }
+                } else {
+                    if sel == 0x10 {
+                        self.packet[self.bit/8] |= 1 << (self.bit%8);
+                    }
+                    self.bit += 1;
+                }
+            },
+            0x30 if self.last_p1&0x20 == 0 && !self.receiving => {
+                self.player = (self.player + 1) % self.players;
+            },
+            _ => ()
+        }
+        self.last_p1 = sel;
+    }
+
+    fn packet_done(&mut self) {
+        if self.packets_left == 0 {
+            self.data.clear();
+            self.packets_left = (self.packet[0]&0x7).max(1);
+        }
+
+        self.data.extend_from_slice(&self.packet);
+        self.packets_left -= 1;
+        if self.packets_left == 0 {
+            self.command();
+        }
+    }
+
+    fn command(&mut self) {
+        let data = std::mem::take(&mut self.data);
+
+        match data[0] >> 3 {
+            0x00 => self.set_palettes(&data, 0, 1),  // PAL01
+            0x01 => self.set_palettes(&data, 2, 3),  // PAL23
+            0x02 => self.set_palettes(&data, 0, 3),  // PAL03
+            0x03 => self.set_palettes(&data, 1, 2),  // PAL12
+            0x04 => self.attr_blk(&data),
+            0x05 => self.attr_lin(&data),
+            0x06 => self.attr_div(&data),
+            0x07 => self.attr_chr(&data),
+            0x0A => {  // PAL_SET
+                for i in 0 .. 4 {
+                    let pos = (read_u16(&data, 1 + i*2) as usize&0x1FF) * 8;
+                    for c in 0 .. 4 {
+                        self.palettes[i][c] = read_u16(&self.system_palettes, pos + c*2);
+                    }
+                }
+                if data[9]&0x80 != 0 {
+                    self.attr_set(data[9]&0x3F);
+                }
+                if data[9]&0x40 != 0 {
+                    self.mask = 0;
+                }
+            },
+            0x11 => {  // MLT_REQ
+                self.players = match data[1]&0x3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1
+                };
+                self.player = 0;
+            },
+            0x16 => {  // ATTR_SET
+                self.attr_set(data[1]&0x3F);
+                if data[1]&0x40 != 0 {
+                    self.mask = 0;
+                }
+            },
+            0x17 => self.mask = data[1]&0x3,  // MASK_EN
+            0x0B | 0x13 | 0x14 | 0x15 => self.transfer = Some(data[0] >> 3 | (data[1]&0x1) << 7),
+            _ => ()  // sound, SNES code upload and the rest aren't emulated
+        }
+    }
+
+    fn set_palettes(&mut self, data: &[u8], a: usize, b: usize) {
+        let color0 = read_u16(data, 1);
+        for p in self.palettes.iter_mut() {
+            p[0] = color0;
+        }
+        for i in 0 .. 3 {
+            self.palettes[a][i+1] = read_u16(data, 3 + i*2);
+            self.palettes[b][i+1] = read_u16(data, 9 + i*2);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1]&0x1F) as usize;
+
+        for set in data[2 ..].chunks(6).take(count) {
+            if set.len() < 6 { break }
+            let control = set[0]&0x7;
+            let inside = set[1]&0x3;
+            let outside = (set[1] >> 4)&0x3;
+            let line = match control {
+                1 => inside,  // only inside given, the border belongs to it
+                4 => outside,
+                _ => (set[1] >> 2)&0x3
+            };
+            let (x1, y1, x2, y2) = (set[2]&0x1F, set[3]&0x1F, set[4]&0x1F, set[5]&0x1F);
+
+            for y in 0 .. 18 {
+                for x in 0 .. 20 {
+                    let in_rect = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_line = in_rect && (x == x1 || x == x2 || y == y1 || y == y2);
+
+                    let palette = if on_line {
+                        if control&0x2 != 0 || control == 1 || control == 4 { Some(line) } else { None }
+                    } else if in_rect {
+                        if control&0x1 != 0 { Some(inside) } else { None }
+                    } else if control&0x4 != 0 {
+                        Some(outside)
+                    } else { None };
+
+                    if let Some(p) = palette {
+                        self.attr_map[y as usize*20 + x as usize] = p;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+
+        for &l in data[2 ..].iter().take(count) {
+            let n = (l&0x1F) as usize;
+            let palette = (l >> 5)&0x3;
+            if l&0x80 != 0 {  // horizontal line
+                if n < 18 {
+                    self.attr_map[n*20 .. n*20+20].fill(palette);
+                }
+            } else if n < 20 {
+                for y in 0 .. 18 {
+                    self.attr_map[y*20 + n] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1]&0x3;  // right / below
+        let before = (data[1] >> 2)&0x3;  // left / above
+        let line = (data[1] >> 4)&0x3;
+        let horizontal = data[1]&0x40 != 0;
+        let coord = data[2] as usize&0x1F;
+
+        for y in 0 .. 18 {
+            for x in 0 .. 20 {
+                let pos = if horizontal { y } else { x };
+                self.attr_map[y*20 + x] = if pos < coord {
+                    before
+                } else if pos == coord {
+                    line
+                } else { after };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = data[1] as usize%20;
+        let mut y = data[2] as usize%18;
+        let count = (read_u16(data, 3) as usize).min(20*18);
+        let vertical = data[5]&0x1 != 0;
+
+        for i in 0 .. count {
+            let byte = match data.get(6 + i/4) {
+                Some(b) => *b,
+                None => break
+            };
+            self.attr_map[y*20 + x] = (byte >> (6 - (i%4)*2))&0x3;
+
+            if vertical {
+                y += 1;
+                if y == 18 { y = 0; x = (x+1)%20; }
+            } else {
+                x += 1;
+                if x == 20 { x = 0; y = (y+1)%18; }
+            }
+        }
+    }
+
+    fn attr_set(&mut self, file: u8) {
+        if file >= 45 { return }
+        let pos = file as usize * 90;
+
+        for i in 0 .. 20*18 {
+            self.attr_map[i] = (self.attr_files[pos + i/4] >> (6 - (i%4)*2))&0x3;
+        }
+    }
+
+    // called on VBLANK, pending *_TRN commands take the frame that was just displayed
+    pub fn vblank(&mut self, vram: &[u8], lcdc: u8) {
+        let transfer = match self.transfer.take() {
+            Some(t) => t,
+            None => return
+        };
+        let data = vram_transfer(vram, lcdc);
+
+        match transfer&0x7F {
+            0x0B => self.system_palettes.copy_from_slice(&data),  // PAL_TRN
+            0x13 => {  // CHR_TRN, lower or upper 128 tiles
+                let pos = (transfer >> 7) as usize * 0x1000;
+                self.border_tiles[pos .. pos+0x1000].copy_from_slice(&data);
+            },
+            0x14 => self.border_map.copy_from_slice(&data),  // PCT_TRN
+            0x15 => self.attr_files.copy_from_slice(&data),  // ATTR_TRN
+            _ => ()
+        }
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = ((y/8)*32 + x/8)*2;
+        let tile = self.border_map[entry] as usize*32;
+        let attr = self.border_map[entry+1];
+
+        let tx = if attr&0x40 != 0 { 7 - x%8 } else { x%8 };
+        let ty = if attr&0x80 != 0 { 7 - y%8 } else { y%8 };
+        let bit = 7 - tx;
+        let planes = [tile + ty*2, tile + ty*2 + 1, tile + 16 + ty*2, tile + 16 + ty*2 + 1];
+        let color = planes.iter().enumerate()
+            .fold(0, |acc, (i, p)| acc | ((self.border_tiles[*p] >> bit)&0x1) << i) as usize;
+
+        if color == 0 {
+            None
+        } else {
+            let palette = ((attr >> 2)&0x3) as usize;  // palettes 4-7
+            Some(read_u16(&self.border_map, 0x800 + palette*32 + color*2))
+        }
+    }
+
+    // composes the 256x224 picture, game screen first and the border over it
+    pub fn render(&self, out: &mut [u8]) {
+        let backdrop = self.palettes[0][0];
+
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                let in_screen = (SCREEN_X .. SCREEN_X+160).contains(&x) && (SCREEN_Y .. SCREEN_Y+144).contains(&y);
+
+                let color = match self.border_pixel(x, y) {
+                    Some(c) => c,
+                    None if in_screen => {
+                        let (sx, sy) = (x - SCREEN_X, y - SCREEN_Y);
+                        match self.mask {
+                            1 => continue,  // frozen, keep what was there
+                            2 => 0,
+                            3 => backdrop,
+                            _ => {
+                                let shade = self.screen[sy*160 + sx] as usize;
+                                if shade == 0 {
+                                    backdrop
+                                } else {
+                                    self.palettes[self.attr_map[(sy/8)*20 + sx/8] as usize][shade]
+                                }
+                            }
+                        }
+                    },
+                    None => backdrop
+                };
+
+                let pos = (y*WIDTH + x)*3;
+                out[pos .. pos+3].copy_from_slice(&rgb(color));
+            }
+        }
+    }
+
+    pub fn save_state(&self, s: &mut State) {
+        for c in self.palettes.iter().flatten() {
+            s.write_u16(*c);
+        }
+        s.write_bytes(&self.attr_map);
+        s.write_u8(self.mask);
+        s.write_bytes(&self.system_palettes);
+        s.write_bytes(&self.attr_files);
+        s.write_bytes(&self.border_tiles);
+        s.write_bytes(&self.border_map);
+        s.write_u8(self.transfer.unwrap_or(0xFF));
+
+        s.write_u8(self.last_p1);
+        s.write_bool(self.receiving);
+        s.write_u8(self.bit as u8);
+        s.write_bytes(&self.packet);
+        s.write_u8(self.data.len() as u8 / 16);
+        for i in 0 .. 7*16 {  // fixed size so snapshots can be diffed
+            s.write_u8(self.data.get(i).copied().unwrap_or(0));
+        }
+        s.write_u8(self.packets_left);
+        s.write_u8(self.players);
+        s.write_u8(self.player);
+    }
+
+    pub fn load_state(&mut self, s: &mut State) {
+        for c in self.palettes.iter_mut().flatten() {
+            *c = s.read_u16();
+        }
+        s.read_bytes(&mut self.attr_map);
+        self.mask = s.read_u8();
+        s.read_bytes(&mut self.system_palettes);
+        s.read_bytes(&mut self.attr_files);
+        s.read_bytes(&mut self.border_tiles);
+        s.read_bytes(&mut self.border_map);
+        self.transfer = match s.read_u8() {
+            0xFF => None,
+            t => Some(t)
+        };
+
+        self.last_p1 = s.read_u8();
+        self.receiving = s.read_bool();
+        self.bit = s.read_u8() as usize;
+        s.read_bytes(&mut self.packet);
+        let len = s.read_u8() as usize * 16;
+        self.data = (0 .. 7*16).map(|_| s.read_u8()).collect();
+        self.data.truncate(len);
+        self.packets_left = s.read_u8();
+        self.players = s.read_u8();
+        self.player = s.read_u8();
+    }
+}
@@ -0,0 +1,74 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: f64 = 4194304. / 70224.;  // ~59.7275Hz
+const DISPLAY_RATE: f64 = 60.;  // max rendered frames per second while unthrottled
+
+pub struct Speed {
+    pub multiplier: f64,  // 1 - real time, < 1 slow motion, 0 - unthrottled
+    pub turbo: f64,       // multiplier used while turbo is held
+    pub turbo_active: bool,
+    pub frameskip: u8,    // frames skipped between rendered ones
+
+    deadline: Instant,
+    last_render: Instant,
+    skipped: u8,
+}
+
+impl Speed {
+    pub fn new() -> Speed {
+        Speed {
+            multiplier: 1.,
+            turbo: 0.,
+            turbo_active: false,
+            frameskip: 0,
+
+            deadline: Instant::now(),
+            last_render: Instant::now(),
+            skipped: 0,
+        }
+    }
+
+    pub fn current(&self) -> f64 {
+        if self.turbo_active { self.turbo } else { self.multiplier }
+    }
+
+    // cpu cycles between audio samples, shortened in slow motion so the stream stays fed
+    pub fn sample_period(&self) -> u32 {
+        let m = self.current();
+        if m > 0. && m < 1. {
+            ((87. * m) as u32).max(1)
+        } else { 87 }
+    }
+
+    // sleeps until the next frame is due, returns whether that frame should be rendered
+    pub fn wait(&mut self) -> bool {
+        let now = Instant::now();
+        let m = self.current();
+
+        if m > 0. {
+            self.deadline += Duration::from_secs_f64(1. / (FRAME_RATE * m));
+            if self.deadline > now {
+                thread::sleep(self.deadline - now);
+            } else if now - self.deadline > Duration::from_millis(100) {
+                self.deadline = now;  // too far behind, don't try to catch up
+            }
+        } else {
+            self.deadline = now;
+        }
+
+        let render = if m == 0. || m > 1. {
+            self.last_render.elapsed().as_secs_f64() >= 1. / DISPLAY_RATE
+        } else {
+            self.skipped >= self.frameskip
+        };
+
+        if render {
+            self.skipped = 0;
+            self.last_render = Instant::now();
+        } else {
+            self.skipped += 1;
+        }
+        render
+    }
+}
@@ -0,0 +1,94 @@
+// flat little endian machine state, written and read back in the same field order
+
+pub struct State {
+    pub data: Vec<u8>,
+    pos: usize,
+}
+
+impl State {
+    pub fn new() -> State {
+        State {
+            data: vec![],
+            pos: 0,
+        }
+    }
+
+    pub fn from(data: Vec<u8>) -> State {
+        State {
+            data,
+            pos: 0,
+        }
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.data.push(v as u8);
+    }
+
+    pub fn write_bytes(&mut self, v: &[u8]) {
+        self.write_u32(v.len() as u32);
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        let v = self.data[self.pos];
+        self.pos += 1;
+        v
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        self.read_u8() as u16 | (self.read_u8() as u16) << 8
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.read_u16() as u32 | (self.read_u16() as u32) << 16
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u8() != 0
+    }
+
+    pub fn read_rest(&mut self) -> Vec<u8> {
+        let rest = self.data[self.pos ..].to_vec();
+        self.pos = self.data.len();
+        rest
+    }
+
+    pub fn read_bytes(&mut self, v: &mut [u8]) {
+        let len = self.read_u32() as usize;
+        v.copy_from_slice(&self.data[self.pos .. self.pos+len]);
+        self.pos += len;
+    }
+
+    // checked reads for files from outside, None once the data runs out
+    pub fn try_read_u8(&mut self) -> Option<u8> {
+        let v = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(v)
+    }
+
+    pub fn try_read_u32(&mut self) -> Option<u32> {
+        let bytes = self.data.get(self.pos .. self.pos+4)?;
+        let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        self.pos += 4;
+        Some(v)
+    }
+
+    pub fn try_read_vec(&mut self) -> Option<Vec<u8>> {
+        let len = self.try_read_u32()? as usize;
+        let v = self.data.get(self.pos .. self.pos.checked_add(len)?)?.to_vec();
+        self.pos += len;
+        Some(v)
+    }
+}
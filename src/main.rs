@@ -2,13 +2,45 @@ use std::path::Path;
 use std::error::Error;
 
 mod emulator;
+mod options;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let opts = options::Options::from_args()?;
     let mut c = emulator::CPU::new();
-    let p = Path::new(&"gbc_bootrom.gbc");
-    let r = Path::new(&"pksilver.gbc");
-    c.memory.load_rom(&r)?;
-    c.memory.load_bootrom(&p)?;
+    c.rewind.max_size = opts.rewind_mb*1024*1024;
+    c.speed.multiplier = opts.speed;
+    c.speed.turbo = opts.turbo;
+    c.speed.frameskip = opts.frameskip;
+    c.memory.load_rom(Path::new(&opts.rom), &opts.patches)?;
+    if let Some(p) = &opts.bootrom {
+        c.memory.load_bootrom(Path::new(p))?;
+    }
+    c.memory.ppu.d.set_color_correction(opts.color_correction);
+    c.memory.ppu.d.blend.mode = opts.blend;
+    c.memory.ppu.d.scaler = opts.scaler;
+    if let Some(spec) = &opts.dmg_palette {
+        c.memory.set_dmg_palette(spec)?;
+    }
+    if opts.sgb {
+        c.memory.enable_sgb()?;
+    } else if opts.cgb || opts.palette.is_some() {
+        c.memory.set_cgb_compat(opts.palette.as_deref())?;
+    }
+
+    if let Some(p) = &opts.record {
+        c.movie.record(p, &c.memory.cart, None)?;
+    } else if let Some(p) = &opts.play {
+        if let Some(data) = c.movie.play(p, &c.memory.cart, true)? {
+            c.load_state(&mut emulator::state::State::from(data));
+        }
+    }
+    if let Some(p) = &opts.video {
+        c.start_video(p)?;
+    }
+
+    for code in opts.cheats.iter() {
+        c.memory.cheats.add_session(code)?;
+    }
     
     {
         let h = &c.memory.ppu.d.thread;
@@ -0,0 +1,94 @@
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::emulator::color::Correction;
+use crate::emulator::blend::BlendMode;
+use crate::emulator::scaler::Scaler;
+
+pub struct Options {
+    pub rom: String,
+    pub bootrom: Option<String>,
+    pub cheats: Vec<String>,
+    pub patches: Vec<PathBuf>,
+    pub rewind_mb: usize,
+    pub speed: f64,
+    pub turbo: f64,
+    pub frameskip: u8,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub sgb: bool,
+    pub cgb: bool,
+    pub palette: Option<String>,
+    pub dmg_palette: Option<String>,
+    pub color_correction: Correction,
+    pub blend: BlendMode,
+    pub scaler: Scaler,
+    pub video: Option<PathBuf>,
+}
+
+impl Options {
+    fn usage() -> String {
+        format!("usage: {} [--bootrom <file>] [--no-bootrom] [--cheat <code>]... [--patch <ips|bps|ups>]...
+    [--rewind-mb <size>] [--speed <x>] [--turbo <x, 0 unthrottled>] [--frameskip <n>]
+    [--record <movie>] [--play <movie>] [--sgb] [--cgb] [--palette <up|down|left|right[+a|+b]>]
+    [--dmg-palette <gray|pocket|green|rrggbb,rrggbb,rrggbb,rrggbb>[/<obp0>/<obp1>]]
+    [--color-correction <raw|fast|accurate>] [--blend <off|mix|decay[:k]>]
+    [--scale <none|<n>x|scale2x|scale3x|xbr|grid[:n]>] [--video <file.gif|file.y4m>] [rom]",
+            env::args().next().unwrap_or_else(|| "sponGB".to_string()))
+    }
+
+    pub fn from_args() -> Result<Options, Box<dyn Error>> {
+        let mut o = Options {
+            rom: "pksilver.gbc".to_string(),
+            bootrom: Some("gbc_bootrom.gbc".to_string()),
+            cheats: vec![],
+            patches: vec![],
+            rewind_mb: 32,
+            speed: 1.,
+            turbo: 0.,
+            frameskip: 0,
+            record: None,
+            play: None,
+            sgb: false,
+            cgb: false,
+            palette: None,
+            dmg_palette: None,
+            color_correction: Correction::Raw,
+            blend: BlendMode::Off,
+            scaler: Scaler::None,
+            video: None,
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} requires a value\n{}", arg, Options::usage()));
+
+            match arg.as_str() {
+                "--bootrom" => o.bootrom = Some(value()?),
+                "--no-bootrom" => o.bootrom = None,
+                "--cheat" => o.cheats.push(value()?),
+                "--patch" => o.patches.push(PathBuf::from(value()?)),
+                "--rewind-mb" => o.rewind_mb = value()?.parse()?,
+                "--speed" => o.speed = value()?.parse()?,
+                "--turbo" => o.turbo = value()?.parse()?,
+                "--frameskip" => o.frameskip = value()?.parse()?,
+                "--record" => o.record = Some(PathBuf::from(value()?)),
+                "--play" => o.play = Some(PathBuf::from(value()?)),
+                "--sgb" => o.sgb = true,
+                "--cgb" => o.cgb = true,
+                "--palette" => o.palette = Some(value()?),
+                "--dmg-palette" => o.dmg_palette = Some(value()?),
+                "--color-correction" => o.color_correction = Correction::parse(&value()?)?,
+                "--blend" => o.blend = BlendMode::parse(&value()?)?,
+                "--scale" => o.scaler = Scaler::parse(&value()?)?,
+                "--video" => o.video = Some(PathBuf::from(value()?)),
+                "-h" | "--help" => return Err(Options::usage().into()),
+                a if a.starts_with("--") => return Err(format!("Unknown option {}\n{}", a, Options::usage()).into()),
+                _ => o.rom = arg
+            }
+        }
+
+        Ok(o)
+    }
+}